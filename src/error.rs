@@ -2,7 +2,7 @@ use std::{error::Error, fmt};
 
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AppError {
     MissingFileArgument,
     InvalidFileFormat,
@@ -13,6 +13,33 @@ pub enum AppError {
     AccountLocked,
     DuplicateRecord,
     DisputeAlreadyExists,
+    TransactionUnderDispute,
+    InvalidFeeSchedule(String),
+    HouseAccountRecord,
+    InvalidCurrency(String),
+    CurrencyMismatch,
+    InvalidRateTable(String),
+    InvalidExchange(String),
+    MissingTimestamp,
+    TimestampOutOfOrder {
+        timestamp: u64,
+        latest: u64,
+    },
+    DisputeWindowExpired,
+    InvalidRiskLimits(String),
+    LimitExceeded(String),
+    InvalidPolicy(String),
+    InvalidInterestSchedule(String),
+    LedgerImbalance(String),
+    ReconciliationBreaks(usize),
+    TransactionConflict(u32),
+    InvalidRecords(usize),
+    InvalidSnapshot(String),
+    BatchHalted {
+        client: u16,
+        tx: u32,
+        reason: String,
+    },
 }
 
 impl fmt::Display for AppError {
@@ -29,8 +56,127 @@ impl fmt::Display for AppError {
             AppError::DisputeAlreadyExists => {
                 write!(f, "Dispute already exists for this transaction")
             }
+            AppError::TransactionUnderDispute => {
+                write!(f, "Transaction is under an open dispute")
+            }
+            AppError::InvalidFeeSchedule(reason) => write!(f, "Invalid fee schedule: {reason}"),
+            AppError::HouseAccountRecord => {
+                write!(f, "Records cannot be applied to the house account")
+            }
+            AppError::InvalidCurrency(code) => write!(f, "Invalid currency: {code}"),
+            AppError::CurrencyMismatch => {
+                write!(f, "Transaction currency does not match the account")
+            }
+            AppError::InvalidRateTable(reason) => write!(f, "Invalid rate table: {reason}"),
+            AppError::InvalidExchange(reason) => write!(f, "Invalid exchange: {reason}"),
+            AppError::MissingTimestamp => {
+                write!(f, "Record needs a timestamp for a windowed risk limit")
+            }
+            AppError::TimestampOutOfOrder { timestamp, latest } => write!(
+                f,
+                "Timestamp {timestamp} is older than the latest timestamp {latest} for the client"
+            ),
+            AppError::DisputeWindowExpired => {
+                write!(f, "Transaction is too old to be disputed")
+            }
+            AppError::InvalidRiskLimits(reason) => write!(f, "Invalid risk limits: {reason}"),
+            AppError::LimitExceeded(limit) => write!(f, "Limit exceeded: {limit}"),
+            AppError::InvalidPolicy(reason) => write!(f, "Invalid policy: {reason}"),
+            AppError::InvalidInterestSchedule(reason) => {
+                write!(f, "Invalid interest schedule: {reason}")
+            }
+            AppError::LedgerImbalance(reason) => write!(f, "Trial balance failed: {reason}"),
+            AppError::ReconciliationBreaks(count) => {
+                write!(f, "Reconciliation found {count} breaks")
+            }
+            AppError::TransactionConflict(tx) => {
+                write!(f, "Transaction {tx} conflicts with an earlier record")
+            }
+            AppError::InvalidRecords(count) => write!(f, "Found {count} invalid records"),
+            AppError::InvalidSnapshot(reason) => write!(f, "Invalid snapshot: {reason}"),
+            AppError::BatchHalted { client, tx, reason } => write!(
+                f,
+                "Batch halted at transaction {tx} for client {client}: {reason}"
+            ),
         }
     }
 }
 
+impl AppError {
+    /// Name of the variant, without its details.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppError::MissingFileArgument => "MissingFileArgument",
+            AppError::InvalidFileFormat => "InvalidFileFormat",
+            AppError::TooManyArguments => "TooManyArguments",
+            AppError::WrongArgument(_) => "WrongArgument",
+            AppError::InvalidTransactionFundAmount => "InvalidTransactionFundAmount",
+            AppError::NotEnoughFunds => "NotEnoughFunds",
+            AppError::AccountLocked => "AccountLocked",
+            AppError::DuplicateRecord => "DuplicateRecord",
+            AppError::DisputeAlreadyExists => "DisputeAlreadyExists",
+            AppError::TransactionUnderDispute => "TransactionUnderDispute",
+            AppError::InvalidFeeSchedule(_) => "InvalidFeeSchedule",
+            AppError::HouseAccountRecord => "HouseAccountRecord",
+            AppError::InvalidCurrency(_) => "InvalidCurrency",
+            AppError::CurrencyMismatch => "CurrencyMismatch",
+            AppError::InvalidRateTable(_) => "InvalidRateTable",
+            AppError::InvalidExchange(_) => "InvalidExchange",
+            AppError::MissingTimestamp => "MissingTimestamp",
+            AppError::TimestampOutOfOrder { .. } => "TimestampOutOfOrder",
+            AppError::DisputeWindowExpired => "DisputeWindowExpired",
+            AppError::InvalidRiskLimits(_) => "InvalidRiskLimits",
+            AppError::LimitExceeded(_) => "LimitExceeded",
+            AppError::InvalidPolicy(_) => "InvalidPolicy",
+            AppError::InvalidInterestSchedule(_) => "InvalidInterestSchedule",
+            AppError::LedgerImbalance(_) => "LedgerImbalance",
+            AppError::ReconciliationBreaks(_) => "ReconciliationBreaks",
+            AppError::TransactionConflict(_) => "TransactionConflict",
+            AppError::InvalidRecords(_) => "InvalidRecords",
+            AppError::InvalidSnapshot(_) => "InvalidSnapshot",
+            AppError::BatchHalted { .. } => "BatchHalted",
+        }
+    }
+}
+
+/// Process exit codes by failure category.
+pub mod exit_code {
+    pub const FAILURE: u8 = 1;
+    pub const USAGE: u8 = 2;
+    pub const INPUT: u8 = 3;
+    pub const CONFIG: u8 = 4;
+    pub const BATCH_HALTED: u8 = 5;
+    pub const INVALID_RECORDS: u8 = 6;
+    pub const RECONCILIATION_BREAKS: u8 = 7;
+    pub const LEDGER_IMBALANCE: u8 = 8;
+}
+
+/// Exit code of the category `err` falls in. Files that cannot be read or
+/// parsed are input failures.
+pub fn exit_code_of(err: &(dyn Error + 'static)) -> u8 {
+    if let Some(err) = err.downcast_ref::<AppError>() {
+        return match err {
+            AppError::MissingFileArgument
+            | AppError::TooManyArguments
+            | AppError::WrongArgument(_) => exit_code::USAGE,
+            AppError::InvalidFileFormat | AppError::InvalidSnapshot(_) => exit_code::INPUT,
+            AppError::InvalidFeeSchedule(_)
+            | AppError::InvalidCurrency(_)
+            | AppError::InvalidRateTable(_)
+            | AppError::InvalidRiskLimits(_)
+            | AppError::InvalidPolicy(_)
+            | AppError::InvalidInterestSchedule(_) => exit_code::CONFIG,
+            AppError::BatchHalted { .. } => exit_code::BATCH_HALTED,
+            AppError::InvalidRecords(_) => exit_code::INVALID_RECORDS,
+            AppError::ReconciliationBreaks(_) => exit_code::RECONCILIATION_BREAKS,
+            AppError::LedgerImbalance(_) => exit_code::LEDGER_IMBALANCE,
+            _ => exit_code::FAILURE,
+        };
+    }
+    if err.is::<std::io::Error>() || err.is::<csv::Error>() || err.is::<csv_async::Error>() {
+        return exit_code::INPUT;
+    }
+    exit_code::FAILURE
+}
+
 impl Error for AppError {}
@@ -6,31 +6,736 @@ pub mod utils;
 #[cfg(test)]
 mod test;
 
+use crate::error::{AppError, exit_code_of};
+use crate::models::currency::Currency;
+use crate::models::exchange::{ExchangeSettings, RateTable, Rounding};
+use crate::models::fee::FeeSchedule;
+use crate::models::interest::InterestSchedule;
+use crate::models::limits::RiskLimits;
+use crate::models::policy::Policy;
+use crate::models::settings::DisputePolicy;
+use crate::service::diff;
+use crate::service::dry_run::DryRunReport;
+use crate::service::generate;
+use crate::service::idempotency::IdempotencyStore;
+use crate::service::journal::Journal;
+use crate::service::listener;
+use crate::service::reconcile;
+use crate::service::server;
+use crate::service::snapshot::Snapshot;
+use crate::service::statement;
 use crate::service::transaction_engine;
-use clap::Parser;
+use crate::service::transaction_engine::{OutputOptions, TransactionEngineService};
+use crate::service::validate;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::process::ExitCode;
+use std::time::Duration;
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    // Without a command the arguments are those of `process`.
+    #[command(flatten)]
+    process: ProcessArgs,
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+// Options accepted before or after any command.
+#[derive(Args)]
+struct GlobalArgs {
+    /// Path to a TOML policy file; other options override its settings
+    #[clap(long, global = true)]
+    config: Option<String>,
+    /// Format of the account balances printed to stdout
+    #[clap(long, global = true, value_enum, default_value_t = OutputFormat::Csv)]
+    output_format: OutputFormat,
+    /// What to log to stderr, such as `debug`; overrides RUST_LOG [default: warn]
+    #[clap(long, global = true)]
+    log_level: Option<String>,
+    /// Format of the logs written to stderr
+    #[clap(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process transactions and print the resulting accounts; the default
+    /// when no command is given
+    Process(ProcessArgs),
+    /// Check every record of a file without applying any; exits with an error
+    /// on any invalid record
+    Validate(ValidateArgs),
+    /// Rebuild the accounts from a journal written with --journal
+    Replay(ReplayArgs),
+    /// Print a client's applied transactions with running balances, or every
+    /// account's state at a point of the input
+    Statement(StatementArgs),
+    /// Process transactions and report where the accounts differ from an
+    /// expected balances file; exits with an error on any break
+    Reconcile(ReconcileArgs),
+    /// Report how each client's balances changed between two runs' account
+    /// outputs, or their journals
+    Diff(DiffArgs),
+    /// Write a reproducible file of random transactions to stdout
+    Generate(GenerateArgs),
+    /// Keep the engine running and accept transactions over HTTP
+    Serve(ServeArgs),
+    /// Keep the engine running and accept CSV rows over TCP connections
+    Listen(ListenArgs),
+}
+
+#[derive(Args)]
+struct ProcessArgs {
+    /// Path to the CSV file containing transactions
+    #[clap(required_unless_present = "print_config", value_parser=utils::validate_file_path)]
+    file_path: Option<String>,
+    #[command(flatten)]
+    engine: EngineArgs,
+    /// Split held funds into disputed and authorized columns in the output
+    #[clap(long, action)]
+    held_breakdown: bool,
+    /// Path of a CSV journal to write every processed record and fee to
+    #[clap(long)]
+    journal: Option<String>,
+    /// Path of a CSV to write the trial balance of the ledger to
+    #[clap(long)]
+    trial_balance: Option<String>,
+    /// Print a summary of processed, rejected and disputed records to stderr
+    #[clap(long, action)]
+    summary: bool,
+    /// Path of a JSON file to write the run's metrics to
+    #[clap(long)]
+    metrics: Option<String>,
+    /// Report bytes read, throughput, clients and rejections on stderr every second
+    #[clap(long, action)]
+    progress: bool,
+    /// Path of a CSV to write the deposits and authorizations later runs may
+    /// still act on to, for --snapshot-transactions
+    #[clap(long)]
+    transaction_index: Option<String>,
+    /// Apply the file to a throwaway state and report what it would do instead
+    /// of printing balances; nothing is written
+    #[clap(long, action, conflicts_with_all = ["journal", "trial_balance", "transaction_index"])]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    /// Path to the CSV file containing transactions
+    #[clap(value_parser=utils::validate_file_path)]
+    file_path: String,
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// Path to a journal written with --journal
+    #[clap(value_parser=utils::validate_file_path)]
+    journal: String,
+    /// Only replay records up to this 1-based line of the original input
+    #[clap(long)]
+    as_of_line: Option<u64>,
+    /// Only replay records timestamped at or before this Unix time
+    #[clap(long)]
+    as_of: Option<u64>,
+    /// Currency the accounts are listed in when no other currency appears
+    #[clap(long, default_value = "USD")]
+    default_currency: Currency,
+}
+
+#[derive(Args)]
+struct GenerateArgs {
+    /// Number of records to generate
+    #[clap(long, default_value_t = 1000)]
+    records: u64,
+    /// Number of clients the records are spread over
+    #[clap(long, default_value_t = 100)]
+    clients: u16,
+    /// Seed of the generator; the same seed generates the same file
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on; port 0 picks a free port
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    addr: String,
+    #[command(flatten)]
+    engine: EngineArgs,
+}
+
+#[derive(Args)]
+struct ListenArgs {
+    /// Address to listen on; port 0 picks a free port
+    #[clap(long, default_value = "127.0.0.1:9000")]
+    addr: String,
+    #[command(flatten)]
+    engine: EngineArgs,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    /// Accounts, or a journal with --journals, of the earlier run
+    #[clap(value_parser=utils::validate_file_path)]
+    before: String,
+    /// Accounts, or a journal with --journals, of the later run
+    #[clap(value_parser=utils::validate_file_path)]
+    after: String,
+    /// Compare journals written with --journal and report the first diverging
+    /// transaction of each changed client
+    #[clap(long, action)]
+    journals: bool,
+    /// Currency of accounts listed without a `currency` column
+    #[clap(long, default_value = "USD")]
+    default_currency: Currency,
+}
+
+#[derive(Args)]
+struct ReconcileArgs {
     /// Path to the CSV file containing transactions
-    #[clap(value_parser, value_parser=utils::validate_file_path)]
+    #[clap(value_parser=utils::validate_file_path)]
+    file_path: String,
+    /// Path to the expected `client,available,held,total,locked` balances
+    #[clap(value_parser=utils::validate_file_path)]
+    expected: String,
+    #[command(flatten)]
+    engine: EngineArgs,
+}
+
+#[derive(Args)]
+struct StatementArgs {
+    /// Path to the CSV file containing transactions, or a journal with --from-journal
+    #[clap(value_parser=utils::validate_file_path)]
     file_path: String,
+    /// Client to print the statement of; without it every account's state is printed
+    #[clap(long)]
+    client: Option<u16>,
+    /// Read a journal written with --journal instead of processing transactions
+    #[clap(long, action)]
+    from_journal: bool,
+    /// Only consider records up to this 1-based line of the input
+    #[clap(long)]
+    as_of_line: Option<u64>,
+    #[command(flatten)]
+    engine: EngineArgs,
+}
+
+// Options controlling how records are applied, shared by every command. Unset
+// options fall back to the policy file, then to the defaults.
+#[derive(Args)]
+struct EngineArgs {
+    /// Print the effective policy as TOML and exit
+    #[clap(long, action)]
+    print_config: bool,
     /// Enable batch mode processing
     /// In batch mode, any error in processing transactions will halt the entire processing.
-    #[clap(long, action)]
+    #[clap(long, action, overrides_with = "no_batch")]
     batch: bool,
+    /// Disable batch mode even if the policy file enables it
+    #[clap(long, action, overrides_with = "batch")]
+    no_batch: bool,
+    /// Answer resubmitted records with their first outcome instead of applying them again,
+    /// and reject records reusing the transaction id of a different record
+    #[clap(long, action, overrides_with = "no_idempotent")]
+    idempotent: bool,
+    /// Disable idempotent ingestion even if the policy file enables it
+    #[clap(
+        long,
+        action,
+        overrides_with = "idempotent",
+        conflicts_with = "idempotency_store"
+    )]
+    no_idempotent: bool,
+    /// Path to a CSV file keeping processed records across runs; implies --idempotent
+    #[clap(long)]
+    idempotency_store: Option<String>,
+    /// Path to a TOML fee schedule; fees, spreads and interest are posted to its house account
+    #[clap(long)]
+    fee_schedule: Option<String>,
+    /// Path to a TOML file of per-client and per-tier risk limits
+    #[clap(long)]
+    limits: Option<String>,
+    /// Disputes the client cannot cover: reject (default), hold what is covered and
+    /// record the shortfall, or negative to hold them in full and flag the account
+    #[clap(long)]
+    dispute_policy: Option<DisputePolicy>,
+    /// Currency of records without a `currency` column [default: USD]
+    #[clap(long)]
+    default_currency: Option<Currency>,
+    /// Path to a TOML interest schedule; interest is paid by the fee schedule's house account
+    #[clap(long)]
+    interest: Option<String>,
+    /// Post accrued interest to every account at this Unix time after the last record
+    #[clap(long)]
+    accrue_at: Option<u64>,
+    /// Path to a CSV of `timestamp,pair,rate` exchange rates
+    #[clap(long)]
+    rates: Option<String>,
+    /// Percentage of each exchange kept by the house account [default: 0]
+    #[clap(long, value_parser = utils::validate_percentage)]
+    exchange_spread: Option<f64>,
+    /// Decimal places exchanged amounts are rounded to [default: 4]
+    #[clap(long)]
+    exchange_decimals: Option<u32>,
+    /// Rounding of exchanged amounts: nearest (default), down or up
+    #[clap(long)]
+    exchange_rounding: Option<Rounding>,
+    /// Seconds a record's timestamp may lag the newest timestamp seen for its client [default: 0]
+    #[clap(long)]
+    reorder_window_secs: Option<u64>,
+    /// Reject disputes raised more than this many days after the deposit
+    #[clap(long)]
+    dispute_window_days: Option<u64>,
+    /// Release authorization holds once they are this many days old
+    #[clap(long)]
+    hold_expiry_days: Option<u64>,
+    /// Only apply records timestamped at or before this Unix time
+    #[clap(long)]
+    as_of: Option<u64>,
+    /// Path to opening balances in the account output format, including `locked`
+    #[clap(long, value_parser=utils::validate_file_path)]
+    snapshot: Option<String>,
+    /// Path to the deposits and authorizations of earlier runs that records may
+    /// still act on, as written with --transaction-index
+    #[clap(long, value_parser=utils::validate_file_path)]
+    snapshot_transactions: Option<String>,
+}
+
+// Value of a `--flag`/`--no-flag` pair, unset when neither is given.
+fn flag(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+impl EngineArgs {
+    /// The policy file's settings with the command line options applied over them.
+    fn policy(&self, config: Option<&str>) -> Result<Policy, Box<dyn std::error::Error>> {
+        let mut policy = match config {
+            Some(path) => Policy::from_file(path)?,
+            None => Policy::default(),
+        };
+        if let Some(batch) = flag(self.batch, self.no_batch) {
+            policy.batch = batch;
+        }
+        if let Some(idempotent) = flag(self.idempotent, self.no_idempotent) {
+            policy.idempotent = idempotent;
+        }
+        policy.idempotent |= self.idempotency_store.is_some();
+        if let Some(dispute_policy) = self.dispute_policy {
+            policy.dispute_policy = dispute_policy;
+        }
+        if let Some(default_currency) = &self.default_currency {
+            policy.default_currency = default_currency.clone();
+        }
+        if let Some(reorder_window_secs) = self.reorder_window_secs {
+            policy.reorder_window_secs = reorder_window_secs;
+        }
+        policy.dispute_window_days = self.dispute_window_days.or(policy.dispute_window_days);
+        policy.hold_expiry_days = self.hold_expiry_days.or(policy.hold_expiry_days);
+        policy.fee_schedule = self.fee_schedule.clone().or(policy.fee_schedule);
+        policy.limits = self.limits.clone().or(policy.limits);
+        policy.rates = self.rates.clone().or(policy.rates);
+        policy.interest = self.interest.clone().or(policy.interest);
+        policy.accrue_at = self.accrue_at.or(policy.accrue_at);
+        policy.as_of = self.as_of.or(policy.as_of);
+        policy.snapshot = self.snapshot.clone().or(policy.snapshot);
+        policy.snapshot_transactions = self
+            .snapshot_transactions
+            .clone()
+            .or(policy.snapshot_transactions);
+        if let Some(exchange_spread) = self.exchange_spread {
+            policy.exchange_spread = exchange_spread;
+        }
+        if let Some(exchange_decimals) = self.exchange_decimals {
+            policy.exchange_decimals = exchange_decimals;
+        }
+        if let Some(exchange_rounding) = self.exchange_rounding {
+            policy.exchange_rounding = exchange_rounding;
+        }
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    // Without `persist`, the idempotency store is only read.
+    fn build(
+        &self,
+        policy: &Policy,
+        persist: bool,
+    ) -> Result<TransactionEngineService, Box<dyn std::error::Error>> {
+        let mut transaction_engine = TransactionEngineService::new()
+            .with_default_currency(policy.default_currency.clone())
+            .with_time_rules(
+                policy.reorder_window_secs,
+                policy.dispute_window_days,
+                policy.hold_expiry_days,
+            )
+            .with_as_of(policy.as_of)
+            .with_dispute_policy(policy.dispute_policy)
+            .with_lock_rules(
+                policy.locked_accepts_deposits,
+                policy.ignore_resolves_after_chargeback,
+            );
+        if let Some(path) = &policy.snapshot {
+            transaction_engine = transaction_engine.with_snapshot(Snapshot::from_files(
+                path,
+                policy.snapshot_transactions.as_deref(),
+                &policy.default_currency,
+            )?);
+        }
+        if let Some(path) = &self.idempotency_store {
+            let store = if persist {
+                IdempotencyStore::open(path)?
+            } else {
+                IdempotencyStore::load(path)?
+            };
+            // Replayed records are not applied again, so the balances they left
+            // must be loaded.
+            if !store.is_empty() && policy.snapshot.is_none() {
+                return Err(AppError::WrongArgument(format!(
+                    "{path} holds records of an earlier run; load the balances they left with --snapshot"
+                )))?;
+            }
+            transaction_engine = transaction_engine.with_idempotency(store);
+        } else if policy.idempotent {
+            transaction_engine = transaction_engine.with_idempotency(IdempotencyStore::in_memory());
+        }
+        if let Some(path) = &policy.fee_schedule {
+            transaction_engine =
+                transaction_engine.with_fee_schedule(FeeSchedule::from_file(path)?);
+        }
+        if let Some(path) = &policy.interest {
+            transaction_engine = transaction_engine
+                .with_interest(InterestSchedule::from_file(path)?, policy.accrue_at);
+        }
+        if let Some(path) = &policy.limits {
+            transaction_engine = transaction_engine.with_risk_limits(RiskLimits::from_file(path)?);
+        }
+        let mut exchange = ExchangeSettings {
+            spread_percent: policy.exchange_spread,
+            decimals: policy.exchange_decimals,
+            rounding: policy.exchange_rounding,
+            ..ExchangeSettings::default()
+        };
+        if let Some(path) = &policy.rates {
+            exchange.rates = RateTable::from_file(path)?;
+        }
+        Ok(transaction_engine.with_exchange_settings(exchange))
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
     let args = Cli::parse();
+    let result = match init_tracing(&args.global) {
+        Ok(()) => run(&args).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::from(exit_code_of(err.as_ref()))
+        }
+    }
+}
 
-    let mut transaction_engine = transaction_engine::TransactionEngineService::new();
-    transaction_engine
-        .begin_transactions_from_file(&args.file_path, args.batch)
-        .await?;
+async fn run(args: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let global = &args.global;
+    match &args.command {
+        None => run_process(&args.process, global).await,
+        Some(Command::Process(process_args)) => run_process(process_args, global).await,
+        Some(Command::Validate(validate_args)) => run_validate(validate_args).await,
+        Some(Command::Replay(replay_args)) => run_replay(replay_args, global).await,
+        Some(Command::Statement(statement_args)) => run_statement(statement_args, global).await,
+        Some(Command::Reconcile(reconcile_args)) => run_reconcile(reconcile_args, global).await,
+        Some(Command::Diff(diff_args)) => run_diff(diff_args).await,
+        Some(Command::Generate(generate_args)) => generate::generate(
+            generate_args.records,
+            generate_args.clients,
+            generate_args.seed,
+        ),
+        Some(Command::Serve(serve_args)) => run_serve(serve_args, global).await,
+        Some(Command::Listen(listen_args)) => run_listen(listen_args, global).await,
+    }
+}
+
+// Logs go to stderr so stdout only carries the command's output.
+fn init_tracing(global: &GlobalArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = match &global.log_level {
+        Some(level) => tracing_subscriber::EnvFilter::try_new(level)
+            .map_err(|err| AppError::WrongArgument(format!("--log-level {level}: {err}")))?,
+        None => tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn")),
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match global.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    Ok(())
+}
+
+async fn run_process(
+    args: &ProcessArgs,
+    global: &GlobalArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let policy = args.engine.policy(global.config.as_deref())?;
+    if args.engine.print_config {
+        print!("{}", policy.to_toml()?);
+        return Ok(());
+    }
+    let file_path = args.file_path.as_deref().unwrap_or_default();
+    if args.dry_run {
+        return run_dry_run(args, global, &policy, file_path).await;
+    }
+
+    let mut transaction_engine = args.engine.build(&policy, true)?;
+    if let Some(path) = &args.journal {
+        transaction_engine = transaction_engine.with_journal(Journal::create(path)?);
+    }
+    if args.progress {
+        transaction_engine = transaction_engine.with_progress(Duration::from_secs(1));
+    }
+    let halted = run_transactions(&mut transaction_engine, file_path, policy.batch).await?;
     // transaction_engine.print_client_accounts().await?;
     transaction_engine
-        .print_client_accounts_four_decimal_places()
+        .print_client_accounts_four_decimal_places(OutputOptions {
+            held_breakdown: args.held_breakdown,
+            fees: policy.fee_schedule.is_some(),
+            overdrawn: policy.dispute_policy == DisputePolicy::Negative,
+            shortfall: policy.dispute_policy != DisputePolicy::Reject,
+            interest: policy.interest.is_some(),
+            json: global.output_format == OutputFormat::Json,
+        })
         .await?; // for four decimal places.
+    let metrics = transaction_engine.metrics.snapshot();
+    if args.summary {
+        eprint!("{}", metrics.summary());
+    }
+    if let Some(path) = &args.metrics {
+        std::fs::write(path, serde_json::to_string_pretty(&metrics)?)?;
+    }
+    if let Some(err) = halted {
+        return Err(err);
+    }
+    if let Some(path) = &args.trial_balance {
+        transaction_engine.write_trial_balance(path)?;
+    }
+    if let Some(path) = &args.transaction_index {
+        transaction_engine.write_transaction_index(path)?;
+    }
+    Ok(())
+}
+
+async fn run_dry_run(
+    args: &ProcessArgs,
+    global: &GlobalArgs,
+    policy: &Policy,
+    file_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut transaction_engine = args
+        .engine
+        .build(policy, false)?
+        .with_journal(Journal::in_memory());
+    if args.progress {
+        transaction_engine = transaction_engine.with_progress(Duration::from_secs(1));
+    }
+    let opening = transaction_engine.accounts();
+    let halted = run_transactions(&mut transaction_engine, file_path, policy.batch).await?;
+    let report = DryRunReport::new(
+        &opening,
+        &transaction_engine.accounts(),
+        &transaction_engine.journal_entries(),
+        halted.is_some(),
+    );
+    match global.output_format {
+        OutputFormat::Csv => print!("{}", report.to_text()),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    let metrics = transaction_engine.metrics.snapshot();
+    if args.summary {
+        eprint!("{}", metrics.summary());
+    }
+    if let Some(path) = &args.metrics {
+        std::fs::write(path, serde_json::to_string_pretty(&metrics)?)?;
+    }
+    match halted {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+async fn run_validate(args: &ValidateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let invalid = validate::validate_file(&args.file_path).await?;
+    validate::print_invalid(&invalid).await?;
+    if !invalid.is_empty() {
+        return Err(AppError::InvalidRecords(invalid.len()))?;
+    }
     Ok(())
 }
+
+async fn run_replay(
+    args: &ReplayArgs,
+    global: &GlobalArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let entries =
+        statement::entries_as_of(Journal::read(&args.journal)?, args.as_of_line, args.as_of);
+    transaction_engine::print_accounts(
+        &statement::accounts_from_journal(&entries),
+        OutputOptions {
+            json: global.output_format == OutputFormat::Json,
+            ..OutputOptions::default()
+        },
+        &args.default_currency,
+    )
+    .await
+}
+
+// Processes a file, returning the error that halted a batch separately so the
+// caller can still print the (empty) account state.
+async fn run_transactions(
+    transaction_engine: &mut transaction_engine::TransactionEngineService,
+    file_path: &str,
+    batch: bool,
+) -> Result<Option<Box<dyn std::error::Error>>, Box<dyn std::error::Error>> {
+    match transaction_engine
+        .begin_transactions_from_file(file_path, batch)
+        .await
+    {
+        Ok(()) => Ok(None),
+        Err(err) if matches!(err.downcast_ref(), Some(AppError::BatchHalted { .. })) => {
+            Ok(Some(err))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+async fn run_reconcile(
+    args: &ReconcileArgs,
+    global: &GlobalArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let policy = args.engine.policy(global.config.as_deref())?;
+    if args.engine.print_config {
+        print!("{}", policy.to_toml()?);
+        return Ok(());
+    }
+    let mut transaction_engine = args.engine.build(&policy, false)?;
+    if let Some(err) =
+        run_transactions(&mut transaction_engine, &args.file_path, policy.batch).await?
+    {
+        return Err(err);
+    }
+    let expected = reconcile::read_accounts(&args.expected, &policy.default_currency)?;
+    let breaks = reconcile::reconcile(&transaction_engine.balances(), &expected);
+    reconcile::print_breaks(&breaks).await?;
+    if !breaks.is_empty() {
+        return Err(AppError::ReconciliationBreaks(breaks.len()))?;
+    }
+    Ok(())
+}
+
+async fn run_serve(
+    args: &ServeArgs,
+    global: &GlobalArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let policy = args.engine.policy(global.config.as_deref())?;
+    if args.engine.print_config {
+        print!("{}", policy.to_toml()?);
+        return Ok(());
+    }
+    server::serve(args.engine.build(&policy, true)?, &args.addr).await
+}
+
+async fn run_listen(
+    args: &ListenArgs,
+    global: &GlobalArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let policy = args.engine.policy(global.config.as_deref())?;
+    if args.engine.print_config {
+        print!("{}", policy.to_toml()?);
+        return Ok(());
+    }
+    listener::listen(args.engine.build(&policy, true)?, &args.addr).await
+}
+
+async fn run_diff(args: &DiffArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if !args.journals {
+        let before = reconcile::read_accounts(&args.before, &args.default_currency)?;
+        let after = reconcile::read_accounts(&args.after, &args.default_currency)?;
+        return diff::print_deltas(&diff::diff_accounts(&before, &after), false).await;
+    }
+
+    let before = Journal::read(&args.before)?;
+    let after = Journal::read(&args.after)?;
+    let mut deltas = diff::diff_accounts(
+        &statement::accounts_from_journal(&before),
+        &statement::accounts_from_journal(&after),
+    );
+    for delta in &mut deltas {
+        delta.first_divergence =
+            diff::first_divergence(&before, &after, delta.client, &delta.currency);
+    }
+    diff::print_deltas(&deltas, true).await
+}
+
+async fn run_statement(
+    args: &StatementArgs,
+    global: &GlobalArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let policy = args.engine.policy(global.config.as_deref())?;
+    if args.engine.print_config {
+        print!("{}", policy.to_toml()?);
+        return Ok(());
+    }
+    let entries = if args.from_journal {
+        Journal::read(&args.file_path)?
+    } else {
+        let mut transaction_engine = args
+            .engine
+            .build(&policy, false)?
+            .with_as_of_line(args.as_of_line)
+            .with_journal(Journal::in_memory());
+        if let Some(err) =
+            run_transactions(&mut transaction_engine, &args.file_path, policy.batch).await?
+        {
+            return Err(err);
+        }
+        transaction_engine.journal_entries()
+    };
+    let entries = statement::entries_as_of(entries, args.as_of_line, policy.as_of);
+
+    match args.client {
+        Some(client) => statement::print_statement(&entries, client).await,
+        None => {
+            transaction_engine::print_accounts(
+                &statement::accounts_from_journal(&entries),
+                OutputOptions {
+                    json: global.output_format == OutputFormat::Json,
+                    ..OutputOptions::default()
+                },
+                &policy.default_currency,
+            )
+            .await
+        }
+    }
+}
@@ -1,2 +1,12 @@
 pub mod account;
+pub mod currency;
+pub mod exchange;
+pub mod fee;
+pub mod interest;
+pub mod ledger;
+pub mod limits;
+pub mod money;
+pub mod policy;
+pub mod settings;
+pub mod tier;
 pub mod transaction;
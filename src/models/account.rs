@@ -4,155 +4,732 @@ use serde_derive::{Deserialize, Serialize};
 
 use crate::{
     error::AppError,
-    models::transaction::{TransactionRecord, TransactionStatus, TransactionType},
+    models::currency::Currency,
+    models::exchange::Rounding,
+    models::interest::InterestSchedule,
+    models::ledger::{Ledger, LedgerAccount},
+    models::limits::Velocity,
+    models::money::Money,
+    models::settings::{DisputePolicy, EngineSettings, SECONDS_PER_DAY},
+    models::transaction::{
+        Dispute, DisputeState, TransactionOutcome, TransactionRecord, TransactionStatus,
+        TransactionType,
+    },
 };
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// A client's funds in one currency. The balances are derived from the
+/// account's ledger and only change through the postings of its operations.
+#[derive(Debug, Clone)]
 pub struct ClientAccount {
     pub client: u16,
+    // Every balance of the account is in this currency.
+    pub currency: Currency,
+    pub locked: bool,
+    // Fees charged to the client so far.
+    pub fees: f64,
+    // Authorizations that have not been released yet.
+    pub open_authorizations: Vec<u32>,
+    // Recent activity checked against the client's risk limits.
+    pub velocity: Velocity,
+    // How far below zero `available` may go on withdrawals, holds and fees.
+    pub credit_limit: Money,
+    // Set once a dispute or chargeback fee took `available` below the credit line.
+    pub overdrawn: bool,
+    // Disputed funds the client could not cover, of disputes not resolved.
+    pub shortfall: f64,
+    // Day interest has been accrued up to.
+    pub interest_since: Option<u64>,
+    // Interest accrued but not posted yet, including rounding residuals.
+    pub accrued_interest: f64,
+    // Interest posted to the client so far.
+    pub interest: f64,
+    // Funds the engine's applied records took out of the account less what
+    // they brought in, tallied apart from the ledger for the trial balance.
+    settled: f64,
+    // Postings every balance is derived from.
+    ledger: Ledger,
+}
+
+/// The columns an account is printed with, or read back from printed output.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AccountBalances {
+    pub client: u16,
+    #[serde(default)]
+    pub currency: Currency,
     pub available: f64,
     pub held: f64,
     pub total: f64,
     pub locked: bool,
+    // Breakdown of `held` by cause.
+    #[serde(skip)]
+    pub held_disputed: f64,
+    #[serde(skip)]
+    pub held_authorized: f64,
+    #[serde(skip)]
+    pub fees: f64,
+    #[serde(skip)]
+    pub overdrawn: bool,
+    // Read back from the `shortfall` column when the output has one.
+    #[serde(default, skip_serializing)]
+    pub shortfall: f64,
+    #[serde(skip)]
+    pub interest: f64,
 }
 
 impl ClientAccount {
-    pub fn new(client: u16) -> Self {
+    pub fn new(client: u16, currency: Currency) -> Self {
         ClientAccount {
             client,
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
+            currency: currency.clone(),
             locked: false,
+            fees: 0.0,
+            open_authorizations: Vec::new(),
+            velocity: Velocity::default(),
+            credit_limit: Money::zero(currency.clone()),
+            overdrawn: false,
+            shortfall: 0.0,
+            interest_since: None,
+            accrued_interest: 0.0,
+            interest: 0.0,
+            settled: 0.0,
+            ledger: Ledger::new(currency),
+        }
+    }
+
+    /// An account carried over from an earlier run, its balances posted
+    /// against the opening ledger account. Fails for a balance in another
+    /// currency.
+    pub fn opened(
+        client: u16,
+        currency: Currency,
+        available: &Money,
+        held_disputed: &Money,
+        held_authorized: &Money,
+        locked: bool,
+        open_authorizations: Vec<u32>,
+    ) -> Result<Self, AppError> {
+        let mut account = ClientAccount::new(client, currency);
+        account.post(LedgerAccount::Opening, LedgerAccount::Available, available)?;
+        account.post(
+            LedgerAccount::Opening,
+            LedgerAccount::HeldDisputed,
+            held_disputed,
+        )?;
+        account.post(
+            LedgerAccount::Opening,
+            LedgerAccount::HeldAuthorized,
+            held_authorized,
+        )?;
+        account.locked = locked;
+        account.open_authorizations = open_authorizations;
+        Ok(account)
+    }
+
+    pub fn available(&self) -> Money {
+        self.ledger.balance(LedgerAccount::Available)
+    }
+
+    // Funds held by open disputes.
+    pub fn held_disputed(&self) -> Money {
+        self.ledger.balance(LedgerAccount::HeldDisputed)
+    }
+
+    // Funds held by open authorizations.
+    pub fn held_authorized(&self) -> Money {
+        self.ledger.balance(LedgerAccount::HeldAuthorized)
+    }
+
+    pub fn held(&self) -> Money {
+        self.ledger
+            .sum(&[LedgerAccount::HeldDisputed, LedgerAccount::HeldAuthorized])
+    }
+
+    pub fn total(&self) -> Money {
+        self.ledger.sum(&[
+            LedgerAccount::Available,
+            LedgerAccount::HeldDisputed,
+            LedgerAccount::HeldAuthorized,
+        ])
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    pub fn settled(&self) -> f64 {
+        self.settled
+    }
+
+    /// Tallies the funds an applied record of `type` moved into or out of the
+    /// account from outside the engine.
+    pub fn record_settlement(&mut self, r#type: &TransactionType, amount: f64) {
+        match r#type {
+            TransactionType::Deposit => self.settled -= amount,
+            TransactionType::Withdrawal | TransactionType::Refund | TransactionType::Capture => {
+                self.settled += amount
+            }
+            _ => {}
         }
     }
 
+    pub fn balances(&self) -> AccountBalances {
+        AccountBalances {
+            client: self.client,
+            currency: self.currency.clone(),
+            available: self.available().amount(),
+            held: self.held().amount(),
+            total: self.total().amount(),
+            locked: self.locked,
+            held_disputed: self.held_disputed().amount(),
+            held_authorized: self.held_authorized().amount(),
+            fees: self.fees,
+            overdrawn: self.overdrawn,
+            shortfall: self.shortfall,
+            interest: self.interest,
+        }
+    }
+
+    /// Applies a record together with its fee from the fee schedule. Either both
+    /// take effect or, on error, the account and the record's transaction status
+    /// are left untouched. The caller checks the client's risk limits first.
+    #[tracing::instrument(level = "debug", skip_all, fields(currency = %self.currency))]
     pub fn apply_transaction_record(
         &mut self,
         transaction: &TransactionRecord,
         processed_transactions: &mut HashMap<u32, TransactionStatus>,
-    ) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        settings: &EngineSettings,
+    ) -> Result<TransactionOutcome, Box<dyn std::error::Error>> {
+        // The house account only moves with the records of other clients.
+        if settings.house_account == Some(self.client) {
+            return Err(AppError::HouseAccountRecord)?;
+        }
         if self.locked {
-            return Err(AppError::AccountLocked)?;
+            match transaction.r#type {
+                TransactionType::Deposit if settings.locked_accepts_deposits => {}
+                TransactionType::Resolve if settings.ignore_resolves_after_chargeback => {
+                    return Ok(TransactionOutcome::default());
+                }
+                _ => return Err(AppError::AccountLocked)?,
+            }
+        }
+        // Amounts of different currencies are never combined in one account.
+        let currency = transaction.settlement_currency(processed_transactions, &self.currency)?;
+        if currency != self.currency {
+            return Err(AppError::CurrencyMismatch)?;
+        }
+        let limits = settings.risk_limits.limits_for(self.client);
+        self.credit_limit = Money::new(
+            limits.credit_limit.unwrap_or_default(),
+            self.currency.clone(),
+        );
+        // Interest on the balance so far accrues whether or not the record applies.
+        self.accrue_interest_before_change(transaction.timestamp, settings.interest.as_ref());
+        let account_before = self.clone();
+        let status_before = processed_transactions.get(&transaction.tx).cloned();
+
+        let result = self
+            .apply_record_balances(transaction, &currency, processed_transactions, settings)
+            .and_then(|moved| {
+                let Some(amount) = moved else {
+                    return Ok(TransactionOutcome::default());
+                };
+                let fee = settings
+                    .fee_schedule
+                    .fee_for(&transaction.r#type, self.client, amount);
+                if fee > 0.0 {
+                    // Chargebacks cannot be refused, so their fee may overdraw the account.
+                    self.charge_fee(
+                        &Money::new(fee, currency.clone()),
+                        transaction.r#type == TransactionType::Chargeback,
+                    )?;
+                }
+                self.overdrawn |= !self.spendable()?.covers(&Money::zero(currency.clone()))?;
+                Ok(TransactionOutcome {
+                    applied: true,
+                    fee,
+                    amount,
+                })
+            });
+
+        match &result {
+            Ok(outcome) => tracing::trace!(
+                applied = outcome.applied,
+                available = self.available().amount(),
+                held = self.held().amount(),
+                total = self.total().amount(),
+                locked = self.locked,
+                "balances after record"
+            ),
+            Err(err) => tracing::trace!(error = %err, "record rolled back"),
+        }
+        if result.is_err() {
+            *self = account_before;
+            match status_before {
+                Some(status) => processed_transactions.insert(transaction.tx, status),
+                None => processed_transactions.remove(&transaction.tx),
+            };
         }
+        result
+    }
+
+    // Moves funds for a record settled in `currency` and returns the amount
+    // moved, or `None` when the record does not reference anything it can act
+    // on and is ignored.
+    fn apply_record_balances(
+        &mut self,
+        transaction: &TransactionRecord,
+        currency: &Currency,
+        processed_transactions: &mut HashMap<u32, TransactionStatus>,
+        settings: &EngineSettings,
+    ) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+        let money = |amount: f64| Money::new(amount, currency.clone());
         match transaction.r#type {
             TransactionType::Deposit => {
                 if processed_transactions.get(&transaction.tx).is_some() {
                     return Err(AppError::DuplicateRecord)?;
                 }
                 if let Some(amount) = transaction.amount {
-                    self.deposit(amount)?;
-                    processed_transactions.insert(
-                        transaction.tx,
-                        TransactionStatus {
-                            deposited: transaction.clone(),
-                            disputed: false,
-                            charged_back: false,
-                            resolved: false,
-                        },
-                    );
+                    self.deposit(&money(amount))?;
+                    processed_transactions.insert(transaction.tx, self.status_for(transaction));
+                    Ok(Some(amount))
                 } else {
-                    Err(AppError::InvalidTransactionFundAmount)?;
+                    Err(AppError::InvalidTransactionFundAmount)?
                 }
-                Ok(self)
             }
             TransactionType::Withdrawal => {
                 if let Some(amount) = transaction.amount {
-                    self.withdraw(amount)?;
+                    self.withdraw(&money(amount))?;
+                    Ok(Some(amount))
                 } else {
-                    Err(AppError::InvalidTransactionFundAmount)?;
+                    Err(AppError::InvalidTransactionFundAmount)?
                 }
-                Ok(self)
             }
             TransactionType::Dispute => {
-                if let Some(status) = processed_transactions.get_mut(&transaction.tx) {
-                    if !status.disputed {
-                        let amount = status
-                            .deposited
-                            .amount
-                            .ok_or(AppError::InvalidTransactionFundAmount)?;
-                        self.dispute(amount)?;
-                        status.disputed = true;
-                    } else {
-                        Err(AppError::DisputeAlreadyExists)?;
+                if let Some(status) = processed_transactions.get_mut(&transaction.tx)
+                    && status.record.r#type == TransactionType::Deposit
+                    && status.record.client == self.client
+                {
+                    if let (Some(window_days), Some(now), Some(deposited_at)) = (
+                        settings.dispute_window_days,
+                        transaction.timestamp,
+                        status.record.timestamp,
+                    ) && now
+                        > deposited_at.saturating_add(window_days.saturating_mul(SECONDS_PER_DAY))
+                    {
+                        return Err(AppError::DisputeWindowExpired)?;
+                    }
+                    let undisputed = status
+                        .undisputed_amount()
+                        .ok_or(AppError::InvalidTransactionFundAmount)?;
+                    if undisputed <= 0.0 {
+                        return Err(AppError::DisputeAlreadyExists)?;
                     }
+                    // Without an amount the whole undisputed remainder is disputed; a
+                    // zero amount is the placeholder older inputs put in the column.
+                    let amount = match transaction.amount.filter(|amount| *amount != 0.0) {
+                        Some(amount)
+                            if !amount.is_finite() || amount < 0.0 || amount > undisputed =>
+                        {
+                            return Err(AppError::InvalidTransactionFundAmount)?;
+                        }
+                        Some(amount) => amount,
+                        None => undisputed,
+                    };
+                    let covered = self.spendable()?.amount().max(0.0).min(amount);
+                    let shortfall = amount - covered;
+                    let held = match settings.dispute_policy {
+                        DisputePolicy::Reject | DisputePolicy::Negative => amount,
+                        DisputePolicy::Hold => covered,
+                    };
+                    self.dispute(
+                        &money(held),
+                        settings.dispute_policy == DisputePolicy::Negative,
+                    )?;
+                    self.shortfall += shortfall;
+                    status.disputes.push(Dispute {
+                        amount,
+                        held,
+                        shortfall,
+                        state: DisputeState::Open,
+                    });
+                    Ok(Some(amount))
+                } else {
+                    Ok(None)
                 }
-                Ok(self)
             }
             TransactionType::Resolve => {
-                if let Some(status) = processed_transactions.get_mut(&transaction.tx) {
-                    if status.disputed && !status.resolved {
-                        let amount = status
-                            .deposited
-                            .amount
-                            .ok_or(AppError::InvalidTransactionFundAmount)?;
-                        self.resolve(amount)?;
-                        status.resolved = true;
-                        status.disputed = false;
-                    }
+                if let Some(status) = processed_transactions.get_mut(&transaction.tx)
+                    && status.record.client == self.client
+                    && let Some(dispute) = status.open_dispute_mut(transaction.dispute)
+                {
+                    self.resolve(&money(dispute.held))?;
+                    self.shortfall -= dispute.shortfall;
+                    dispute.state = DisputeState::Resolved;
+                    Ok(Some(dispute.amount))
+                } else {
+                    Ok(None)
                 }
-                Ok(self)
             }
             TransactionType::Chargeback => {
-                if let Some(status) = processed_transactions.get_mut(&transaction.tx) {
-                    if status.disputed && !status.charged_back {
-                        let amount = status
-                            .deposited
-                            .amount
-                            .ok_or(AppError::InvalidTransactionFundAmount)?;
-                        self.chargeback(amount)?;
-                        status.charged_back = true;
-                        status.disputed = false;
+                if let Some(status) = processed_transactions.get_mut(&transaction.tx)
+                    && status.record.client == self.client
+                    && let Some(dispute) = status.open_dispute_mut(transaction.dispute)
+                {
+                    // A shortfall stays owed by the client after the chargeback.
+                    self.chargeback(&money(dispute.held))?;
+                    dispute.state = DisputeState::ChargedBack;
+                    Ok(Some(dispute.amount))
+                } else {
+                    Ok(None)
+                }
+            }
+            TransactionType::Authorize => {
+                if processed_transactions.get(&transaction.tx).is_some() {
+                    return Err(AppError::DuplicateRecord)?;
+                }
+                if let Some(amount) = transaction.amount {
+                    self.authorize(&money(amount))?;
+                    processed_transactions.insert(transaction.tx, self.status_for(transaction));
+                    self.open_authorizations.push(transaction.tx);
+                    Ok(Some(amount))
+                } else {
+                    Err(AppError::InvalidTransactionFundAmount)?
+                }
+            }
+            TransactionType::Capture => {
+                if let Some(status) = processed_transactions.get_mut(&transaction.tx)
+                    && status.record.r#type == TransactionType::Authorize
+                    && status.record.client == self.client
+                    && !status.released
+                {
+                    let authorized = status
+                        .record
+                        .amount
+                        .ok_or(AppError::InvalidTransactionFundAmount)?;
+                    let remaining = authorized - status.captured;
+                    // Without an amount the whole remaining hold is captured.
+                    let amount = transaction.amount.unwrap_or(remaining);
+                    if amount <= 0.0 || amount > remaining {
+                        return Err(AppError::InvalidTransactionFundAmount)?;
+                    }
+                    self.capture(&money(amount))?;
+                    status.captured += amount;
+                    Ok(Some(amount))
+                } else {
+                    Ok(None)
+                }
+            }
+            TransactionType::Release => {
+                if let Some(status) = processed_transactions.get_mut(&transaction.tx)
+                    && status.record.r#type == TransactionType::Authorize
+                    && status.record.client == self.client
+                    && !status.released
+                {
+                    let authorized = status
+                        .record
+                        .amount
+                        .ok_or(AppError::InvalidTransactionFundAmount)?;
+                    let remaining = authorized - status.captured;
+                    self.release(&money(remaining))?;
+                    status.released = true;
+                    self.open_authorizations.retain(|tx| *tx != transaction.tx);
+                    Ok(Some(remaining))
+                } else {
+                    Ok(None)
+                }
+            }
+            // Only the debit leg; the engine credits the target currency account.
+            TransactionType::Exchange => {
+                if let Some(amount) = transaction.amount {
+                    self.exchange_out(&money(amount))?;
+                    Ok(Some(amount))
+                } else {
+                    Err(AppError::InvalidTransactionFundAmount)?
+                }
+            }
+            TransactionType::Accrue => match &settings.interest {
+                Some(schedule) if settings.house_account.is_some() => {
+                    Ok(Some(self.post_interest(schedule.decimals)))
+                }
+                _ => Ok(None),
+            },
+            TransactionType::Refund => {
+                if let Some(status) = processed_transactions.get_mut(&transaction.tx)
+                    && status.record.r#type == TransactionType::Deposit
+                    && status.record.client == self.client
+                {
+                    if status.disputed_amount() > 0.0 {
+                        return Err(AppError::TransactionUnderDispute)?;
                     }
+                    let refundable = status
+                        .undisputed_amount()
+                        .ok_or(AppError::InvalidTransactionFundAmount)?;
+                    // Without an amount the whole refundable remainder is refunded.
+                    let amount = transaction.amount.unwrap_or(refundable);
+                    if amount <= 0.0 || amount > refundable {
+                        return Err(AppError::InvalidTransactionFundAmount)?;
+                    }
+                    self.withdraw(&money(amount))?;
+                    status.refunded += amount;
+                    Ok(Some(amount))
+                } else {
+                    Ok(None)
                 }
-                Ok(self)
             }
         }
     }
-    pub fn deposit(&mut self, amount: f64) -> Result<&mut Self, Box<dyn std::error::Error>> {
-        self.available += amount;
-        self.total += amount;
+
+    /// Releases what is left of every authorization hold at least `expiry`
+    /// seconds older than `now`, returning the released tx IDs and amounts.
+    pub fn release_expired_holds(
+        &mut self,
+        now: u64,
+        expiry: u64,
+        interest: Option<&InterestSchedule>,
+        processed_transactions: &mut HashMap<u32, TransactionStatus>,
+    ) -> Vec<(u32, f64)> {
+        let mut released = Vec::new();
+        let open_authorizations = std::mem::take(&mut self.open_authorizations);
+        for tx in open_authorizations {
+            let Some(status) = processed_transactions.get_mut(&tx) else {
+                continue;
+            };
+            let expired = status
+                .record
+                .timestamp
+                .is_some_and(|authorized_at| authorized_at.saturating_add(expiry) <= now);
+            if !expired {
+                self.open_authorizations.push(tx);
+                continue;
+            }
+            let remaining = status.record.amount.unwrap_or_default() - status.captured;
+            let currency = status.record.currency.as_ref().unwrap_or(&self.currency);
+            let currency = currency.clone();
+            self.accrue_interest_before_change(Some(now), interest);
+            if remaining > 0.0 && self.release(&Money::new(remaining, currency)).is_ok() {
+                released.push((tx, remaining));
+            }
+            status.released = true;
+        }
+        released
+    }
+
+    /// Accrues interest on a positive `available` for every whole day between
+    /// the last accrual and `now`. The first call only starts the clock.
+    pub fn accrue_interest(&mut self, now: u64, schedule: &InterestSchedule) {
+        let today = now / SECONDS_PER_DAY;
+        if let Some(since) = self.interest_since
+            && today > since
+        {
+            self.accrued_interest +=
+                self.available().amount().max(0.0) * schedule.rate_for(self.client) / 100.0
+                    * schedule.day_count.year_fraction(since, today);
+        }
+        self.interest_since = Some(self.interest_since.map_or(today, |since| since.max(today)));
+    }
+
+    /// Accrues interest on the balance so far up to `now`, if both are set,
+    /// before `available` changes.
+    pub fn accrue_interest_before_change(
+        &mut self,
+        now: Option<u64>,
+        interest: Option<&InterestSchedule>,
+    ) {
+        if let (Some(schedule), Some(now)) = (interest, now) {
+            self.accrue_interest(now, schedule);
+        }
+    }
+
+    /// Credits the accrued interest rounded down to `decimals` and returns it.
+    /// The rounding residual stays accrued for the next posting.
+    pub fn post_interest(&mut self, decimals: u32) -> f64 {
+        // Drop float noise first so e.g. 0.29999999999 posts as 0.30.
+        let accrued = (self.accrued_interest * 1e9).round() / 1e9;
+        let posted = Rounding::Down.round(accrued, decimals);
+        self.accrued_interest -= posted;
+        self.house_transfer(&Money::new(posted, self.currency.clone()))
+            .expect("interest accrues in the account's currency");
+        self.interest += posted;
+        posted
+    }
+
+    // Status tracked for a deposit or authorization, tagged with the account's currency.
+    fn status_for(&self, transaction: &TransactionRecord) -> TransactionStatus {
+        let mut record = transaction.clone();
+        record.currency = Some(self.currency.clone());
+        TransactionStatus::new(record)
+    }
+
+    pub fn charge_fee(
+        &mut self,
+        fee: &Money,
+        allow_overdraft: bool,
+    ) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        if self.spendable()?.covers(fee)? || allow_overdraft {
+            self.post(LedgerAccount::Available, LedgerAccount::HouseTransfers, fee)?;
+            self.fees += fee.amount();
+            Ok(self)
+        } else {
+            Err(AppError::NotEnoughFunds)?
+        }
+    }
+
+    // Available funds plus what is left of the credit line.
+    fn spendable(&self) -> Result<Money, AppError> {
+        self.available().checked_add(&self.credit_limit)
+    }
+
+    /// Records a posting in the account's ledger. Fails, leaving the account
+    /// untouched, for an amount in another currency.
+    fn post(
+        &mut self,
+        from: LedgerAccount,
+        to: LedgerAccount,
+        amount: &Money,
+    ) -> Result<(), AppError> {
+        self.ledger.post(from, to, amount)
+    }
+
+    pub fn deposit(&mut self, amount: &Money) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        self.post(LedgerAccount::Settlement, LedgerAccount::Available, amount)?;
         Ok(self)
     }
 
-    pub fn withdraw(&mut self, amount: f64) -> Result<&mut Self, Box<dyn std::error::Error>> {
-        if self.available >= amount {
-            self.available -= amount;
-            self.total -= amount;
+    pub fn withdraw(&mut self, amount: &Money) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        if self.spendable()?.covers(amount)? {
+            self.post(LedgerAccount::Available, LedgerAccount::Settlement, amount)?;
             Ok(self)
         } else {
             Err(AppError::NotEnoughFunds)?
         }
     }
 
-    pub fn dispute(&mut self, amount: f64) -> Result<&mut Self, Box<dyn std::error::Error>> {
-        if self.available >= amount {
-            self.available -= amount;
-            self.held += amount;
+    /// Debit leg of an exchange out of the account's currency.
+    pub fn exchange_out(
+        &mut self,
+        amount: &Money,
+    ) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        if self.spendable()?.covers(amount)? {
+            self.post(LedgerAccount::Available, LedgerAccount::Exchange, amount)?;
             Ok(self)
         } else {
             Err(AppError::NotEnoughFunds)?
         }
     }
 
-    pub fn resolve(&mut self, amount: f64) -> Result<&mut Self, Box<dyn std::error::Error>> {
-        if self.held >= amount {
-            self.held -= amount;
-            self.available += amount;
+    /// Credit leg of an exchange into the account's currency.
+    pub fn exchange_in(&mut self, amount: &Money) -> Result<&mut Self, AppError> {
+        self.post(LedgerAccount::Exchange, LedgerAccount::Available, amount)?;
+        Ok(self)
+    }
+
+    /// Funds received from (or, when negative, paid to) another client's
+    /// account by the house account.
+    pub fn house_transfer(&mut self, amount: &Money) -> Result<&mut Self, AppError> {
+        self.post(
+            LedgerAccount::HouseTransfers,
+            LedgerAccount::Available,
+            amount,
+        )?;
+        Ok(self)
+    }
+
+    pub fn dispute(
+        &mut self,
+        amount: &Money,
+        allow_overdraft: bool,
+    ) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        if self.spendable()?.covers(amount)? || allow_overdraft {
+            self.post(
+                LedgerAccount::Available,
+                LedgerAccount::HeldDisputed,
+                amount,
+            )?;
+            Ok(self)
+        } else {
+            Err(AppError::NotEnoughFunds)?
+        }
+    }
+
+    pub fn resolve(&mut self, amount: &Money) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        if self
+            .ledger
+            .balance(LedgerAccount::HeldDisputed)
+            .covers(amount)?
+        {
+            self.post(
+                LedgerAccount::HeldDisputed,
+                LedgerAccount::Available,
+                amount,
+            )?;
             Ok(self)
         } else {
             Err(AppError::NotEnoughFunds)?
         }
     }
 
-    pub fn chargeback(&mut self, amount: f64) -> Result<&mut Self, Box<dyn std::error::Error>> {
-        if self.held >= amount {
-            self.held -= amount;
-            self.total -= amount;
+    pub fn chargeback(&mut self, amount: &Money) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        if self
+            .ledger
+            .balance(LedgerAccount::HeldDisputed)
+            .covers(amount)?
+        {
+            self.post(
+                LedgerAccount::HeldDisputed,
+                LedgerAccount::ChargebackLoss,
+                amount,
+            )?;
             self.locked = true;
             Ok(self)
         } else {
             Err(AppError::NotEnoughFunds)?
         }
     }
+
+    pub fn authorize(&mut self, amount: &Money) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        if self.spendable()?.covers(amount)? {
+            self.post(
+                LedgerAccount::Available,
+                LedgerAccount::HeldAuthorized,
+                amount,
+            )?;
+            Ok(self)
+        } else {
+            Err(AppError::NotEnoughFunds)?
+        }
+    }
+
+    pub fn capture(&mut self, amount: &Money) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        if self
+            .ledger
+            .balance(LedgerAccount::HeldAuthorized)
+            .covers(amount)?
+        {
+            self.post(
+                LedgerAccount::HeldAuthorized,
+                LedgerAccount::Settlement,
+                amount,
+            )?;
+            Ok(self)
+        } else {
+            Err(AppError::NotEnoughFunds)?
+        }
+    }
+
+    pub fn release(&mut self, amount: &Money) -> Result<&mut Self, Box<dyn std::error::Error>> {
+        if self
+            .ledger
+            .balance(LedgerAccount::HeldAuthorized)
+            .covers(amount)?
+        {
+            self.post(
+                LedgerAccount::HeldAuthorized,
+                LedgerAccount::Available,
+                amount,
+            )?;
+            Ok(self)
+        } else {
+            Err(AppError::NotEnoughFunds)?
+        }
+    }
 }
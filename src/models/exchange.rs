@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    models::{currency::Currency, money::Money},
+};
+
+/// Exchange rates loaded from a local CSV of `timestamp,pair,rate` rows, where
+/// a pair `EUR/USD` with rate `1.08` means one EUR buys 1.08 USD.
+#[derive(Debug, Default, Clone)]
+pub struct RateTable {
+    // Key: (from, to), Value: (timestamp, rate) sorted by timestamp
+    rates: HashMap<(Currency, Currency), Vec<(u64, f64)>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateRow {
+    timestamp: u64,
+    pair: String,
+    rate: f64,
+}
+
+impl RateTable {
+    pub fn from_file(file_path: &str) -> Result<Self, AppError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(file_path)
+            .map_err(|err| AppError::InvalidRateTable(err.to_string()))?;
+
+        let mut table = RateTable::default();
+        for row in rdr.deserialize::<RateRow>() {
+            let row = row.map_err(|err| AppError::InvalidRateTable(err.to_string()))?;
+            let (from, to) = row
+                .pair
+                .split_once('/')
+                .ok_or_else(|| AppError::InvalidRateTable(format!("invalid pair {}", row.pair)))?;
+            if !row.rate.is_finite() || row.rate <= 0.0 {
+                return Err(AppError::InvalidRateTable(format!(
+                    "rate for {} is not a positive number",
+                    row.pair
+                )));
+            }
+            table
+                .rates
+                .entry((Currency::new(from)?, Currency::new(to)?))
+                .or_default()
+                .push((row.timestamp, row.rate));
+        }
+        for rates in table.rates.values_mut() {
+            rates.sort_by_key(|(timestamp, _)| *timestamp);
+        }
+        Ok(table)
+    }
+
+    /// Latest rate from `from` to `to` published at or before `at` (any time
+    /// when `at` is unset). Falls back to the inverse of the opposite pair.
+    pub fn rate(&self, from: &Currency, to: &Currency, at: Option<u64>) -> Option<f64> {
+        let latest = |rates: &Vec<(u64, f64)>| {
+            rates
+                .iter()
+                .rev()
+                .find(|(timestamp, _)| at.is_none_or(|at| *timestamp <= at))
+                .map(|(_, rate)| *rate)
+        };
+        self.rates
+            .get(&(from.clone(), to.clone()))
+            .and_then(latest)
+            .or_else(|| {
+                self.rates
+                    .get(&(to.clone(), from.clone()))
+                    .and_then(latest)
+                    .map(|rate| 1.0 / rate)
+            })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rounding {
+    #[default]
+    Nearest,
+    Down,
+    Up,
+}
+
+impl std::str::FromStr for Rounding {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Rounding::Nearest),
+            "down" => Ok(Rounding::Down),
+            "up" => Ok(Rounding::Up),
+            _ => Err(AppError::WrongArgument(s.to_string())),
+        }
+    }
+}
+
+impl Rounding {
+    pub fn round(&self, amount: f64, decimals: u32) -> f64 {
+        let scale = 10f64.powi(decimals as i32);
+        match self {
+            Rounding::Nearest => (amount * scale).round() / scale,
+            Rounding::Down => (amount * scale).floor() / scale,
+            Rounding::Up => (amount * scale).ceil() / scale,
+        }
+    }
+}
+
+/// How `exchange` records are converted.
+#[derive(Debug, Clone)]
+pub struct ExchangeSettings {
+    pub rates: RateTable,
+    pub decimals: u32,
+    pub rounding: Rounding,
+    // Percentage of the converted amount kept by the house account.
+    pub spread_percent: f64,
+}
+
+impl Default for ExchangeSettings {
+    fn default() -> Self {
+        ExchangeSettings {
+            rates: RateTable::default(),
+            decimals: 4,
+            rounding: Rounding::default(),
+            spread_percent: 0.0,
+        }
+    }
+}
+
+/// Amounts credited in the target currency by one exchange.
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    pub credited: Money,
+    pub spread: Money,
+}
+
+impl ExchangeSettings {
+    /// Converts `amount` into `to` at the latest rate at `at`; the only way an
+    /// amount changes currency.
+    pub fn convert(
+        &self,
+        amount: &Money,
+        to: &Currency,
+        at: Option<u64>,
+    ) -> Result<Conversion, AppError> {
+        let from = amount.currency();
+        let rate = self
+            .rates
+            .rate(from, to, at)
+            .ok_or_else(|| AppError::InvalidExchange(format!("no rate for {from}/{to}")))?;
+        let converted = self.rounding.round(amount.amount() * rate, self.decimals);
+        let spread = self
+            .rounding
+            .round(converted * self.spread_percent / 100.0, self.decimals);
+        Ok(Conversion {
+            credited: Money::new(converted - spread, to.clone()),
+            spread: Money::new(spread, to.clone()),
+        })
+    }
+}
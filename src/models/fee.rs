@@ -0,0 +1,121 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    models::{tier::Tiers, transaction::TransactionType},
+};
+
+/// Fee schedule loaded from a TOML file.
+///
+/// ```toml
+/// house_account = 9999
+///
+/// [tiers]
+/// gold = [1, 2]
+///
+/// [[fees]]
+/// type = "withdrawal"
+/// flat = 0.5
+/// percent = 1.0
+/// min = 1.0
+/// max = 10.0
+///
+/// [[fees]]
+/// type = "withdrawal"
+/// tier = "gold"
+/// flat = 0.0
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct FeeSchedule {
+    // Client ID credited with every fee charged, and paying interest and
+    // receiving exchange spreads. Records of this client are rejected.
+    pub house_account: u16,
+    #[serde(default)]
+    pub tiers: Tiers,
+    #[serde(default)]
+    pub fees: Vec<FeeRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FeeRule {
+    pub r#type: TransactionType,
+    // Applies to every client without a tier-specific rule when unset.
+    pub tier: Option<String>,
+    #[serde(default)]
+    pub flat: f64,
+    // Percentage of the transaction amount, e.g. 1.5 for 1.5%.
+    #[serde(default)]
+    pub percent: f64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl FeeSchedule {
+    pub fn from_file(file_path: &str) -> Result<Self, AppError> {
+        let content = std::fs::read_to_string(file_path)
+            .map_err(|err| AppError::InvalidFeeSchedule(err.to_string()))?;
+        let schedule: FeeSchedule = toml::from_str(&content)
+            .map_err(|err| AppError::InvalidFeeSchedule(err.to_string()))?;
+        schedule.validate()?;
+        Ok(schedule)
+    }
+
+    fn validate(&self) -> Result<(), AppError> {
+        self.tiers
+            .validate()
+            .map_err(AppError::InvalidFeeSchedule)?;
+        for rule in &self.fees {
+            if rule.flat < 0.0 || rule.percent < 0.0 {
+                return Err(AppError::InvalidFeeSchedule(format!(
+                    "negative fee for {:?}",
+                    rule.r#type
+                )));
+            }
+            if let (Some(min), Some(max)) = (rule.min, rule.max)
+                && min > max
+            {
+                return Err(AppError::InvalidFeeSchedule(format!(
+                    "min fee {min} is greater than max fee {max} for {:?}",
+                    rule.r#type
+                )));
+            }
+            if let Some(tier) = &rule.tier
+                && !self.tiers.contains(tier)
+            {
+                return Err(AppError::InvalidFeeSchedule(format!("unknown tier {tier}")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fee owed for a transaction of `amount`, rounded to four decimal places.
+    /// A tier-specific rule takes precedence over the general rule for the type.
+    pub fn fee_for(&self, r#type: &TransactionType, client: u16, amount: f64) -> f64 {
+        if client == self.house_account {
+            return 0.0;
+        }
+        let tier = self.tiers.tier_of(client);
+        let rule = self
+            .fees
+            .iter()
+            .filter(|rule| rule.r#type == *r#type)
+            .find(|rule| tier.is_some() && rule.tier.as_deref() == tier)
+            .or_else(|| {
+                self.fees
+                    .iter()
+                    .find(|rule| rule.r#type == *r#type && rule.tier.is_none())
+            });
+        let Some(rule) = rule else {
+            return 0.0;
+        };
+
+        let mut fee = rule.flat + amount * rule.percent / 100.0;
+        if let Some(min) = rule.min {
+            fee = fee.max(min);
+        }
+        if let Some(max) = rule.max {
+            fee = fee.min(max);
+        }
+        (fee * 10000.0).round() / 10000.0
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{error::AppError, models::tier::Tiers};
+
+/// Interest paid on positive `available` balances, loaded from a TOML file.
+///
+/// ```toml
+/// day_count = "act/365"
+/// decimals = 2
+/// annual_percent = 1.0
+///
+/// [tiers]
+/// gold = [1, 2]
+///
+/// [tier_rates]
+/// gold = 2.5
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InterestSchedule {
+    #[serde(default)]
+    pub day_count: DayCount,
+    // Decimal places interest is posted with; the remainder stays accrued.
+    #[serde(default = "default_decimals")]
+    pub decimals: u32,
+    // Annual rate of clients without a tier rate, e.g. 1.5 for 1.5%.
+    #[serde(default)]
+    pub annual_percent: f64,
+    #[serde(default)]
+    pub tiers: Tiers,
+    // Key: tier name, Value: annual rate in percent
+    #[serde(default)]
+    pub tier_rates: HashMap<String, f64>,
+}
+
+fn default_decimals() -> u32 {
+    4
+}
+
+/// How the fraction of a year between two days is counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DayCount {
+    #[default]
+    #[serde(rename = "act/365")]
+    Act365,
+    #[serde(rename = "act/360")]
+    Act360,
+    #[serde(rename = "30/360")]
+    Thirty360,
+}
+
+impl DayCount {
+    /// Fraction of a year from `from_day` to `to_day`, both in days since the Unix epoch.
+    pub fn year_fraction(&self, from_day: u64, to_day: u64) -> f64 {
+        let days = to_day.saturating_sub(from_day) as f64;
+        match self {
+            DayCount::Act365 => days / 365.0,
+            DayCount::Act360 => days / 360.0,
+            DayCount::Thirty360 => {
+                let (y1, m1, d1) = civil_from_days(from_day);
+                let (y2, m2, d2) = civil_from_days(to_day);
+                let d1 = d1.min(30);
+                let d2 = if d1 == 30 { d2.min(30) } else { d2 };
+                let days = 360 * (y2 - y1) + 30 * (m2 - m1) + (d2 - d1);
+                days as f64 / 360.0
+            }
+        }
+    }
+}
+
+// (year, month, day) of a day since the Unix epoch in the proleptic Gregorian calendar.
+fn civil_from_days(days: u64) -> (i64, i64, i64) {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl InterestSchedule {
+    pub fn from_file(file_path: &str) -> Result<Self, AppError> {
+        let content = std::fs::read_to_string(file_path)
+            .map_err(|err| AppError::InvalidInterestSchedule(err.to_string()))?;
+        let schedule: InterestSchedule = toml::from_str(&content)
+            .map_err(|err| AppError::InvalidInterestSchedule(err.to_string()))?;
+        schedule.validate()?;
+        Ok(schedule)
+    }
+
+    fn validate(&self) -> Result<(), AppError> {
+        self.tiers
+            .validate()
+            .map_err(AppError::InvalidInterestSchedule)?;
+        if self.decimals > 10 {
+            return Err(AppError::InvalidInterestSchedule(format!(
+                "decimals {} is above 10",
+                self.decimals
+            )));
+        }
+        if self.annual_percent < 0.0 {
+            return Err(AppError::InvalidInterestSchedule(
+                "negative annual_percent".to_string(),
+            ));
+        }
+        for (tier, rate) in &self.tier_rates {
+            if !self.tiers.contains(tier) {
+                return Err(AppError::InvalidInterestSchedule(format!(
+                    "unknown tier {tier}"
+                )));
+            }
+            if *rate < 0.0 {
+                return Err(AppError::InvalidInterestSchedule(format!(
+                    "negative rate for tier {tier}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Annual rate of `client` in percent.
+    pub fn rate_for(&self, client: u16) -> f64 {
+        self.tiers
+            .tier_of(client)
+            .and_then(|tier| self.tier_rates.get(tier))
+            .copied()
+            .unwrap_or(self.annual_percent)
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::error::AppError;
+use crate::models::{currency::Currency, money::Money};
+
+/// Accounts of a client's ledger in one currency. The client's own balances
+/// are positive when the client holds funds; the other accounts are the
+/// counterparts funds come from and go to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LedgerAccount {
+    Available,
+    HeldDisputed,
+    HeldAuthorized,
+    // Funds entering and leaving the engine: deposits, withdrawals, captures, refunds.
+    Settlement,
+    // Funds returned to the payer by chargebacks.
+    ChargebackLoss,
+    // Funds moved between clients and the house account: fees, spreads, interest.
+    HouseTransfers,
+    // Counterpart of exchanges into and out of the currency.
+    Exchange,
+    // Balances carried over from a snapshot of an earlier run.
+    Opening,
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerAccount::Available => write!(f, "available"),
+            LedgerAccount::HeldDisputed => write!(f, "held_disputed"),
+            LedgerAccount::HeldAuthorized => write!(f, "held_authorized"),
+            LedgerAccount::Settlement => write!(f, "settlement"),
+            LedgerAccount::ChargebackLoss => write!(f, "chargeback_loss"),
+            LedgerAccount::HouseTransfers => write!(f, "house_transfers"),
+            LedgerAccount::Exchange => write!(f, "exchange"),
+            LedgerAccount::Opening => write!(f, "opening"),
+        }
+    }
+}
+
+/// Double-entry ledger in a single currency: every posting moves an amount
+/// from one account to another, so the balances always sum to zero.
+#[derive(Debug, Default, Clone)]
+pub struct Ledger {
+    currency: Currency,
+    balances: BTreeMap<LedgerAccount, f64>,
+}
+
+impl Ledger {
+    pub fn new(currency: Currency) -> Self {
+        Ledger {
+            currency,
+            balances: BTreeMap::new(),
+        }
+    }
+
+    /// Posts `amount`, which must be in the ledger's currency.
+    pub fn post(
+        &mut self,
+        from: LedgerAccount,
+        to: LedgerAccount,
+        amount: &Money,
+    ) -> Result<(), AppError> {
+        if *amount.currency() != self.currency {
+            return Err(AppError::CurrencyMismatch);
+        }
+        *self.balances.entry(from).or_default() -= amount.amount();
+        *self.balances.entry(to).or_default() += amount.amount();
+        Ok(())
+    }
+
+    pub fn balance(&self, account: LedgerAccount) -> Money {
+        Money::new(
+            self.balances.get(&account).copied().unwrap_or_default(),
+            self.currency.clone(),
+        )
+    }
+
+    /// Combined balance of `accounts`.
+    pub fn sum(&self, accounts: &[LedgerAccount]) -> Money {
+        let sum = accounts
+            .iter()
+            .filter_map(|account| self.balances.get(account))
+            .fold(0.0, |sum, balance| sum + balance);
+        Money::new(sum, self.currency.clone())
+    }
+
+    pub fn balances(&self) -> impl Iterator<Item = (LedgerAccount, Money)> + '_ {
+        self.balances
+            .iter()
+            .map(|(account, balance)| (*account, Money::new(*balance, self.currency.clone())))
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    models::{
+        settings::SECONDS_PER_DAY,
+        tier::Tiers,
+        transaction::{TransactionRecord, TransactionType},
+    },
+};
+
+/// Risk limits loaded from a TOML rules file. Client limits override tier
+/// limits, which override the defaults, field by field.
+///
+/// ```toml
+/// [tiers]
+/// vip = [1, 2]
+///
+/// [default]
+/// max_withdrawal = 1000.0
+/// max_daily_withdrawal = 5000.0
+/// max_transactions = 100
+/// transaction_window_secs = 3600
+/// max_deposit = 10000.0
+/// deposit_window_secs = 86400
+///
+/// [tier.vip]
+/// credit_limit = 500.0
+/// max_withdrawal = 50000.0
+///
+/// [client.42]
+/// max_withdrawal = 10.0
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct RiskLimits {
+    #[serde(default)]
+    pub tiers: Tiers,
+    #[serde(default)]
+    pub default: Limits,
+    // Key: tier name
+    #[serde(default)]
+    pub tier: HashMap<String, Limits>,
+    // Key: client ID
+    #[serde(default)]
+    pub client: HashMap<String, Limits>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Limits {
+    pub max_withdrawal: Option<f64>,
+    pub max_daily_withdrawal: Option<f64>,
+    pub max_transactions: Option<usize>,
+    pub transaction_window_secs: Option<u64>,
+    pub max_deposit: Option<f64>,
+    pub deposit_window_secs: Option<u64>,
+    // How far below zero `available` may go.
+    pub credit_limit: Option<f64>,
+}
+
+impl Limits {
+    // Fields set in `self` win over those in `fallback`.
+    fn or(&self, fallback: &Limits) -> Limits {
+        Limits {
+            max_withdrawal: self.max_withdrawal.or(fallback.max_withdrawal),
+            max_daily_withdrawal: self.max_daily_withdrawal.or(fallback.max_daily_withdrawal),
+            max_transactions: self.max_transactions.or(fallback.max_transactions),
+            transaction_window_secs: self
+                .transaction_window_secs
+                .or(fallback.transaction_window_secs),
+            max_deposit: self.max_deposit.or(fallback.max_deposit),
+            deposit_window_secs: self.deposit_window_secs.or(fallback.deposit_window_secs),
+            credit_limit: self.credit_limit.or(fallback.credit_limit),
+        }
+    }
+}
+
+impl RiskLimits {
+    pub fn from_file(file_path: &str) -> Result<Self, AppError> {
+        let content = std::fs::read_to_string(file_path)
+            .map_err(|err| AppError::InvalidRiskLimits(err.to_string()))?;
+        let limits: RiskLimits =
+            toml::from_str(&content).map_err(|err| AppError::InvalidRiskLimits(err.to_string()))?;
+        limits.validate()?;
+        Ok(limits)
+    }
+
+    fn validate(&self) -> Result<(), AppError> {
+        let all_limits = std::iter::once(&self.default)
+            .chain(self.tier.values())
+            .chain(self.client.values());
+        for limits in all_limits {
+            if limits
+                .credit_limit
+                .is_some_and(|credit_limit| credit_limit < 0.0)
+            {
+                return Err(AppError::InvalidRiskLimits(
+                    "negative credit limit".to_string(),
+                ));
+            }
+        }
+        for client in self.client.keys() {
+            client
+                .parse::<u16>()
+                .map_err(|_| AppError::InvalidRiskLimits(format!("invalid client ID {client}")))?;
+        }
+        for tier in self.tier.keys() {
+            if !self.tiers.contains(tier) {
+                return Err(AppError::InvalidRiskLimits(format!("unknown tier {tier}")));
+            }
+        }
+        self.tiers.validate().map_err(AppError::InvalidRiskLimits)
+    }
+
+    /// Effective limits of `client`.
+    pub fn limits_for(&self, client: u16) -> Limits {
+        let tier_limits = self
+            .tiers
+            .tier_of(client)
+            .and_then(|tier| self.tier.get(tier))
+            .map(|limits| limits.or(&self.default))
+            .unwrap_or_else(|| self.default.clone());
+        self.client
+            .get(&client.to_string())
+            .map(|limits| limits.or(&tier_limits))
+            .unwrap_or(tier_limits)
+    }
+}
+
+/// Recent activity of an account. Windowed limits are evaluated against the
+/// activity of all of a client's accounts, valued in the currency the limits
+/// are set in.
+#[derive(Debug, Default, Clone)]
+pub struct Velocity {
+    // Timestamps of applied records.
+    pub transactions: VecDeque<u64>,
+    // (timestamp, value) of applied deposits.
+    pub deposits: VecDeque<(u64, f64)>,
+    // Day number and value withdrawn on that day.
+    pub daily_withdrawal: (u64, f64),
+}
+
+impl Limits {
+    /// Whether a limit over a time window applies to records of `r#type`,
+    /// which then need a timestamp.
+    pub fn windowed(&self, r#type: &TransactionType) -> bool {
+        self.max_transactions.is_some()
+            || match r#type {
+                TransactionType::Withdrawal => self.max_daily_withdrawal.is_some(),
+                TransactionType::Deposit => self.max_deposit.is_some(),
+                _ => false,
+            }
+    }
+
+    /// Whether a limit applies to the amount of records of `r#type`.
+    pub fn limits_amount(&self, r#type: &TransactionType) -> bool {
+        match r#type {
+            TransactionType::Withdrawal => {
+                self.max_withdrawal.is_some() || self.max_daily_withdrawal.is_some()
+            }
+            TransactionType::Deposit => self.max_deposit.is_some(),
+            _ => false,
+        }
+    }
+}
+
+impl Velocity {
+    /// Rejects `transaction`, worth `value`, if applying it would breach
+    /// `limits` given the activity of all of the client's accounts.
+    pub fn check(
+        velocities: &[&Velocity],
+        transaction: &TransactionRecord,
+        value: f64,
+        limits: &Limits,
+    ) -> Result<(), AppError> {
+        let now = match transaction.timestamp {
+            Some(timestamp) => timestamp,
+            None if limits.windowed(&transaction.r#type) => {
+                return Err(AppError::MissingTimestamp);
+            }
+            None => 0,
+        };
+
+        if let Some(max) = limits.max_transactions {
+            let window = limits.transaction_window_secs.unwrap_or(SECONDS_PER_DAY);
+            let count = velocities
+                .iter()
+                .flat_map(|velocity| &velocity.transactions)
+                .filter(|timestamp| now.saturating_sub(**timestamp) < window)
+                .count();
+            if count >= max {
+                return Err(AppError::LimitExceeded(format!(
+                    "more than {max} transactions in {window}s"
+                )));
+            }
+        }
+        match transaction.r#type {
+            TransactionType::Withdrawal => {
+                if let Some(max) = limits.max_withdrawal
+                    && value > max
+                {
+                    return Err(AppError::LimitExceeded(format!("withdrawal above {max}")));
+                }
+                if let Some(max) = limits.max_daily_withdrawal {
+                    let withdrawn: f64 = velocities
+                        .iter()
+                        .map(|velocity| velocity.daily_withdrawal)
+                        .filter(|(day, _)| *day == now / SECONDS_PER_DAY)
+                        .map(|(_, withdrawn)| withdrawn)
+                        .sum();
+                    if withdrawn + value > max {
+                        return Err(AppError::LimitExceeded(format!(
+                            "daily withdrawals above {max}"
+                        )));
+                    }
+                }
+            }
+            TransactionType::Deposit => {
+                if let Some(max) = limits.max_deposit {
+                    let window = limits.deposit_window_secs.unwrap_or(SECONDS_PER_DAY);
+                    let deposited: f64 = velocities
+                        .iter()
+                        .flat_map(|velocity| &velocity.deposits)
+                        .filter(|(timestamp, _)| now.saturating_sub(*timestamp) < window)
+                        .map(|(_, value)| value)
+                        .sum();
+                    if deposited + value > max {
+                        return Err(AppError::LimitExceeded(format!(
+                            "deposits above {max} in {window}s"
+                        )));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Counts an applied `transaction` worth `value`, forgetting activity
+    /// older than the longest window in `limits`. Records without a timestamp
+    /// are only applied when no windowed limit applies to them.
+    pub fn record(&mut self, transaction: &TransactionRecord, value: f64, limits: &Limits) {
+        let Some(now) = transaction.timestamp else {
+            return;
+        };
+
+        if limits.max_transactions.is_some() {
+            self.transactions.push_back(now);
+            let window = limits.transaction_window_secs.unwrap_or(SECONDS_PER_DAY);
+            while self
+                .transactions
+                .front()
+                .is_some_and(|timestamp| now.saturating_sub(*timestamp) >= window)
+            {
+                self.transactions.pop_front();
+            }
+        }
+        match transaction.r#type {
+            TransactionType::Withdrawal if limits.max_daily_withdrawal.is_some() => {
+                let day = now / SECONDS_PER_DAY;
+                if self.daily_withdrawal.0 == day {
+                    self.daily_withdrawal.1 += value;
+                } else if day > self.daily_withdrawal.0 {
+                    self.daily_withdrawal = (day, value);
+                }
+            }
+            TransactionType::Deposit if limits.max_deposit.is_some() => {
+                self.deposits.push_back((now, value));
+                let window = limits.deposit_window_secs.unwrap_or(SECONDS_PER_DAY);
+                while self
+                    .deposits
+                    .front()
+                    .is_some_and(|(timestamp, _)| now.saturating_sub(*timestamp) >= window)
+                {
+                    self.deposits.pop_front();
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::ops::Neg;
+
+use crate::{error::AppError, models::currency::Currency};
+
+/// An amount tagged with its currency. Adding, subtracting or comparing two
+/// amounts goes through the checked methods below, which fail at runtime with
+/// `CurrencyMismatch` when the currencies differ. `amount` hands back the bare
+/// number, so tallies kept as plain numbers are only as safe as the code
+/// keeping them in one currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Money {
+    amount: f64,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: f64, currency: Currency) -> Self {
+        Money { amount, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money::new(0.0, currency)
+    }
+
+    pub fn amount(&self) -> f64 {
+        self.amount
+    }
+
+    pub fn currency(&self) -> &Currency {
+        &self.currency
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money, AppError> {
+        self.same_currency(other)?;
+        Ok(Money::new(
+            self.amount + other.amount,
+            self.currency.clone(),
+        ))
+    }
+
+    pub fn checked_sub(&self, other: &Money) -> Result<Money, AppError> {
+        self.same_currency(other)?;
+        Ok(Money::new(
+            self.amount - other.amount,
+            self.currency.clone(),
+        ))
+    }
+
+    /// Whether this amount is at least `other`.
+    pub fn covers(&self, other: &Money) -> Result<bool, AppError> {
+        self.same_currency(other)?;
+        Ok(self.amount >= other.amount)
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), AppError> {
+        if self.currency == other.currency {
+            Ok(())
+        } else {
+            Err(AppError::CurrencyMismatch)
+        }
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money::new(-self.amount, self.currency)
+    }
+}
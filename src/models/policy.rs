@@ -0,0 +1,120 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    models::{currency::Currency, exchange::Rounding, settings::DisputePolicy},
+};
+
+/// Every rule a run is processed with, loaded from a TOML policy file and
+/// overridden by command line options. Printed by `--print-config`, it can be
+/// passed back with `--config` to reproduce a run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    // Any rejected record halts the run and discards every account.
+    pub batch: bool,
+    // A resubmitted record is answered with its first outcome, and a record
+    // reusing the tx id of a different one is rejected.
+    pub idempotent: bool,
+    // Currency of records without a `currency` column.
+    pub default_currency: Currency,
+    pub dispute_policy: DisputePolicy,
+    // Deposits into a locked account are applied instead of rejected.
+    pub locked_accepts_deposits: bool,
+    // Resolves of a locked account are ignored instead of rejected.
+    pub ignore_resolves_after_chargeback: bool,
+    pub reorder_window_secs: u64,
+    pub dispute_window_days: Option<u64>,
+    pub hold_expiry_days: Option<u64>,
+    // Paths of the TOML fee schedule, risk limits and interest schedule, and
+    // of the CSV rate table.
+    pub fee_schedule: Option<String>,
+    pub limits: Option<String>,
+    pub interest: Option<String>,
+    pub rates: Option<String>,
+    pub exchange_spread: f64,
+    pub exchange_decimals: u32,
+    pub exchange_rounding: Rounding,
+    // Interest is posted to every account at this Unix time after the last record.
+    pub accrue_at: Option<u64>,
+    // Records timestamped after this Unix time are not applied.
+    pub as_of: Option<u64>,
+    // Paths of the opening balances and of the index of the deposits records
+    // may still act on.
+    pub snapshot: Option<String>,
+    pub snapshot_transactions: Option<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            batch: false,
+            idempotent: false,
+            default_currency: Currency::default(),
+            dispute_policy: DisputePolicy::default(),
+            locked_accepts_deposits: false,
+            ignore_resolves_after_chargeback: false,
+            reorder_window_secs: 0,
+            dispute_window_days: None,
+            hold_expiry_days: None,
+            fee_schedule: None,
+            limits: None,
+            interest: None,
+            rates: None,
+            exchange_spread: 0.0,
+            exchange_decimals: 4,
+            exchange_rounding: Rounding::default(),
+            accrue_at: None,
+            as_of: None,
+            snapshot: None,
+            snapshot_transactions: None,
+        }
+    }
+}
+
+impl Policy {
+    pub fn from_file(file_path: &str) -> Result<Self, AppError> {
+        let content = std::fs::read_to_string(file_path)
+            .map_err(|err| AppError::InvalidPolicy(err.to_string()))?;
+        let policy: Policy =
+            toml::from_str(&content).map_err(|err| AppError::InvalidPolicy(err.to_string()))?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn validate(&self) -> Result<(), AppError> {
+        if !(0.0..100.0).contains(&self.exchange_spread) {
+            return Err(AppError::InvalidPolicy(format!(
+                "exchange_spread {} is not a percentage below 100",
+                self.exchange_spread
+            )));
+        }
+        // Spreads and interest move funds to and from the fee schedule's house account.
+        if self.fee_schedule.is_none() && (self.exchange_spread > 0.0 || self.interest.is_some()) {
+            return Err(AppError::InvalidPolicy(
+                "exchange_spread and interest need a fee_schedule with a house_account".to_string(),
+            ));
+        }
+        if self.accrue_at.is_some() && self.interest.is_none() {
+            return Err(AppError::InvalidPolicy(
+                "accrue_at needs an interest schedule".to_string(),
+            ));
+        }
+        if self.snapshot_transactions.is_some() && self.snapshot.is_none() {
+            return Err(AppError::InvalidPolicy(
+                "snapshot_transactions needs a snapshot".to_string(),
+            ));
+        }
+        if self.exchange_decimals > 10 {
+            return Err(AppError::InvalidPolicy(format!(
+                "exchange_decimals {} is above 10",
+                self.exchange_decimals
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String, AppError> {
+        toml::to_string(self).map_err(|err| AppError::InvalidPolicy(err.to_string()))
+    }
+}
@@ -0,0 +1,68 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::models::{
+    currency::Currency, exchange::ExchangeSettings, fee::FeeSchedule, interest::InterestSchedule,
+    limits::RiskLimits,
+};
+
+pub const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Rules every record is applied with.
+#[derive(Debug, Default, Clone)]
+pub struct EngineSettings {
+    pub fee_schedule: FeeSchedule,
+    // Client ID of the fee schedule's house account, when one is loaded.
+    pub house_account: Option<u16>,
+    pub exchange: ExchangeSettings,
+    pub risk_limits: RiskLimits,
+    // Interest accrues only when a schedule is set.
+    pub interest: Option<InterestSchedule>,
+    // Interest is posted to every account at this Unix time after the last record.
+    pub accrue_at: Option<u64>,
+    // What a dispute does when the client cannot cover the disputed amount.
+    pub dispute_policy: DisputePolicy,
+    // Deposits into a locked account are applied instead of rejected.
+    pub locked_accepts_deposits: bool,
+    // Resolves of a locked account are ignored instead of rejected.
+    pub ignore_resolves_after_chargeback: bool,
+    // Currency of records without a `currency` column.
+    pub default_currency: Currency,
+    // Seconds a record may be older than the newest record seen for its client.
+    pub reorder_window: u64,
+    // Deposits older than this can no longer be disputed.
+    pub dispute_window_days: Option<u64>,
+    // Authorization holds are released once they are this old.
+    pub hold_expiry_days: Option<u64>,
+    // Records timestamped after this are not applied.
+    pub as_of: Option<u64>,
+    // Records after this 1-based line of the input are not read.
+    pub as_of_line: Option<u64>,
+}
+
+/// How a dispute of funds the client has already spent is handled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisputePolicy {
+    // The dispute fails with `NotEnoughFunds`.
+    #[default]
+    Reject,
+    // What the client can cover is held and the rest recorded as a shortfall.
+    Hold,
+    // The full amount is held, taking `available` below the credit line; the
+    // uncovered part is recorded as a shortfall.
+    Negative,
+}
+
+impl std::str::FromStr for DisputePolicy {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(DisputePolicy::Reject),
+            "hold" => Ok(DisputePolicy::Hold),
+            "negative" => Ok(DisputePolicy::Negative),
+            _ => Err(AppError::WrongArgument(s.to_string())),
+        }
+    }
+}
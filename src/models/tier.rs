@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Named groups of clients, shared by the fee schedule, risk limits and
+/// interest schedule. A client belongs to at most one tier.
+///
+/// ```toml
+/// [tiers]
+/// gold = [1, 2]
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct Tiers(
+    // Key: tier name, Value: client IDs in the tier
+    HashMap<String, Vec<u16>>,
+);
+
+impl Tiers {
+    /// Fails with the reason when a client is in more than one tier.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut assigned: HashMap<u16, &str> = HashMap::new();
+        for (tier, clients) in &self.0 {
+            for client in clients {
+                if let Some(other) = assigned.insert(*client, tier) {
+                    return Err(format!(
+                        "client {client} is in both tier {other} and tier {tier}"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, tier: &str) -> bool {
+        self.0.contains_key(tier)
+    }
+
+    pub fn tier_of(&self, client: u16) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(_, clients)| clients.contains(&client))
+            .map(|(tier, _)| tier.as_str())
+    }
+}
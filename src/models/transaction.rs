@@ -1,11 +1,88 @@
+use std::collections::HashMap;
+use std::fmt;
+
 use serde_derive::{Deserialize, Serialize};
 
+use crate::{error::AppError, models::currency::Currency};
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TransactionRecord {
     pub r#type: TransactionType,
     pub client: u16,
     pub tx: u32,
     pub amount: Option<f64>,
+    // Selects which dispute of `tx` a resolve or chargeback acts on (1-based).
+    pub dispute: Option<u32>,
+    pub currency: Option<Currency>,
+    // Target currency of an exchange.
+    pub to_currency: Option<Currency>,
+    // Unix time in seconds.
+    pub timestamp: Option<u64>,
+}
+
+impl TransactionRecord {
+    /// Checks what can be known about the record on its own, without any
+    /// account state: required amounts, signs and exchange targets.
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self
+            .amount
+            .is_some_and(|amount| !amount.is_finite() || amount < 0.0)
+        {
+            return Err(AppError::InvalidTransactionFundAmount);
+        }
+        let needs_amount = matches!(
+            self.r#type,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Authorize
+                | TransactionType::Exchange
+        );
+        if needs_amount && self.amount.is_none_or(|amount| amount == 0.0) {
+            return Err(AppError::InvalidTransactionFundAmount);
+        }
+        if self.r#type == TransactionType::Exchange {
+            match (&self.currency, &self.to_currency) {
+                (_, None) => {
+                    return Err(AppError::InvalidExchange("missing to_currency".to_string()));
+                }
+                (Some(from), Some(to)) if from == to => {
+                    return Err(AppError::InvalidExchange(format!(
+                        "cannot exchange {from} into {to}"
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Currency the record moves funds in. Records acting on an earlier
+    /// transaction settle in that transaction's currency; any other record uses
+    /// its own currency, or `default` when it has none.
+    pub fn settlement_currency(
+        &self,
+        processed_transactions: &HashMap<u32, TransactionStatus>,
+        default: &Currency,
+    ) -> Result<Currency, AppError> {
+        let referenced = match self.r#type {
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Authorize
+            | TransactionType::Exchange
+            | TransactionType::Accrue => None,
+            _ => processed_transactions
+                .get(&self.tx)
+                .and_then(|status| status.record.currency.clone()),
+        };
+        match (referenced, &self.currency) {
+            (Some(referenced), Some(currency)) if referenced != *currency => {
+                Err(AppError::CurrencyMismatch)
+            }
+            (Some(referenced), _) => Ok(referenced),
+            (None, Some(currency)) => Ok(currency.clone()),
+            (None, None) => Ok(default.clone()),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -13,15 +90,95 @@ pub struct Transaction {
     pub transactions: Vec<TransactionRecord>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TransactionStatus {
-    pub deposited: TransactionRecord,
-    pub disputed: bool,
-    pub charged_back: bool,
-    pub resolved: bool,
+    // The deposit or authorization this status tracks.
+    pub record: TransactionRecord,
+    // Every dispute raised against the deposit, in the order they were raised.
+    pub disputes: Vec<Dispute>,
+    pub refunded: f64,
+    // Authorization holds only.
+    pub captured: f64,
+    pub released: bool,
+}
+
+impl TransactionStatus {
+    pub fn new(record: TransactionRecord) -> Self {
+        TransactionStatus {
+            record,
+            disputes: Vec::new(),
+            refunded: 0.0,
+            captured: 0.0,
+            released: false,
+        }
+    }
+
+    pub fn disputed_amount(&self) -> f64 {
+        self.amount_in_state(DisputeState::Open)
+    }
+
+    pub fn charged_back_amount(&self) -> f64 {
+        self.amount_in_state(DisputeState::ChargedBack)
+    }
+
+    /// Part of the deposit that is not under an open dispute, charged back or refunded.
+    pub fn undisputed_amount(&self) -> Option<f64> {
+        self.record.amount.map(|amount| {
+            amount - self.disputed_amount() - self.charged_back_amount() - self.refunded
+        })
+    }
+
+    /// The open dispute selected by `id`, or the oldest open dispute without one.
+    pub fn open_dispute_mut(&mut self, id: Option<u32>) -> Option<&mut Dispute> {
+        match id {
+            Some(id) => self
+                .disputes
+                .get_mut((id as usize).checked_sub(1)?)
+                .filter(|dispute| dispute.state == DisputeState::Open),
+            None => self
+                .disputes
+                .iter_mut()
+                .find(|dispute| dispute.state == DisputeState::Open),
+        }
+    }
+
+    fn amount_in_state(&self, state: DisputeState) -> f64 {
+        self.disputes
+            .iter()
+            .filter(|dispute| dispute.state == state)
+            // Folding from 0.0 rather than summing keeps an empty total at 0.0, not -0.0.
+            .fold(0.0, |total, dispute| total + dispute.amount)
+    }
+}
+
+/// Result of applying a record to a client account.
+#[derive(Debug, Default, Clone)]
+pub struct TransactionOutcome {
+    // False when the record referenced nothing it could act on and was ignored.
+    pub applied: bool,
+    pub fee: f64,
+    // Funds the record moved, before its fee.
+    pub amount: f64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Dispute {
+    pub amount: f64,
+    // Part of `amount` moved into `held`.
+    pub held: f64,
+    // Part of `amount` the client's funds did not cover when it was raised.
+    pub shortfall: f64,
+    pub state: DisputeState,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum DisputeState {
+    Open,
+    Resolved,
+    ChargedBack,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -29,4 +186,51 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Authorize,
+    Capture,
+    #[serde(alias = "void")]
+    Release,
+    Refund,
+    Exchange,
+    // Posts the interest accrued on the account.
+    Accrue,
+}
+
+impl std::str::FromStr for TransactionType {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(TransactionType::Deposit),
+            "withdrawal" => Ok(TransactionType::Withdrawal),
+            "dispute" => Ok(TransactionType::Dispute),
+            "resolve" => Ok(TransactionType::Resolve),
+            "chargeback" => Ok(TransactionType::Chargeback),
+            "authorize" => Ok(TransactionType::Authorize),
+            "capture" => Ok(TransactionType::Capture),
+            "release" | "void" => Ok(TransactionType::Release),
+            "refund" => Ok(TransactionType::Refund),
+            "exchange" => Ok(TransactionType::Exchange),
+            "accrue" => Ok(TransactionType::Accrue),
+            _ => Err(AppError::WrongArgument(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionType::Deposit => write!(f, "deposit"),
+            TransactionType::Withdrawal => write!(f, "withdrawal"),
+            TransactionType::Dispute => write!(f, "dispute"),
+            TransactionType::Resolve => write!(f, "resolve"),
+            TransactionType::Chargeback => write!(f, "chargeback"),
+            TransactionType::Authorize => write!(f, "authorize"),
+            TransactionType::Capture => write!(f, "capture"),
+            TransactionType::Release => write!(f, "release"),
+            TransactionType::Refund => write!(f, "refund"),
+            TransactionType::Exchange => write!(f, "exchange"),
+            TransactionType::Accrue => write!(f, "accrue"),
+        }
+    }
 }
@@ -1 +1,14 @@
+pub mod diff;
+pub mod dry_run;
+pub mod generate;
+pub mod idempotency;
+pub mod journal;
+pub mod listener;
+pub mod metrics;
+pub mod progress;
+pub mod reconcile;
+pub mod server;
+pub mod snapshot;
+pub mod statement;
 pub mod transaction_engine;
+pub mod validate;
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+
+use tokio::io::AsyncWriteExt;
+
+use crate::models::account::AccountBalances;
+use crate::models::currency::Currency;
+use crate::service::journal::JournalEntry;
+
+/// How an account changed from one run to another.
+#[derive(Debug, Clone)]
+pub struct AccountDelta {
+    pub client: u16,
+    pub currency: Currency,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    // (before, after) when the lock changed.
+    pub locked: Option<(bool, bool)>,
+    // First journal entry of the account that differs between the runs.
+    pub first_divergence: Option<String>,
+}
+
+/// Changed accounts, ordered by client and currency. An account missing
+/// from one run counts as an empty, unlocked account there.
+pub fn diff_accounts(before: &[AccountBalances], after: &[AccountBalances]) -> Vec<AccountDelta> {
+    let by_key = |accounts: &[AccountBalances]| {
+        accounts
+            .iter()
+            .map(|account| ((account.client, account.currency.clone()), account.clone()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let before = by_key(before);
+    let after = by_key(after);
+    let round = |amount: f64| (amount * 10000.0).round() / 10000.0;
+
+    let mut keys: Vec<&(u16, Currency)> = before.keys().chain(after.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let (client, currency) = key.clone();
+            let empty = AccountBalances {
+                client,
+                currency: currency.clone(),
+                ..AccountBalances::default()
+            };
+            let old = before.get(key).unwrap_or(&empty);
+            let new = after.get(key).unwrap_or(&empty);
+            let delta = AccountDelta {
+                client,
+                currency,
+                available: round(new.available) - round(old.available),
+                held: round(new.held) - round(old.held),
+                total: round(new.total) - round(old.total),
+                locked: (old.locked != new.locked).then_some((old.locked, new.locked)),
+                first_divergence: None,
+            };
+            let changed = round(delta.available) != 0.0
+                || round(delta.held) != 0.0
+                || round(delta.total) != 0.0
+                || delta.locked.is_some();
+            changed.then_some(delta)
+        })
+        .collect()
+}
+
+/// Describes the first journal entry of `client` in `currency` where the runs
+/// differ, such as `line 4 withdrawal tx 3`.
+pub fn first_divergence(
+    before: &[JournalEntry],
+    after: &[JournalEntry],
+    client: u16,
+    currency: &Currency,
+) -> Option<String> {
+    let of_account = |entries: &[JournalEntry]| -> Vec<JournalEntry> {
+        entries
+            .iter()
+            .filter(|entry| entry.client == client && entry.currency == *currency)
+            .cloned()
+            .collect()
+    };
+    let before = of_account(before);
+    let after = of_account(after);
+    let round = |amount: f64| (amount * 10000.0).round() as i64;
+    let key = |entry: &JournalEntry| {
+        (
+            entry.line,
+            entry.r#type.clone(),
+            entry.tx,
+            entry.outcome,
+            entry.amount.map(round),
+            round(entry.fee),
+            round(entry.available),
+            round(entry.held),
+            round(entry.total),
+            entry.locked,
+        )
+    };
+
+    (0..before.len().max(after.len()))
+        .find(|index| before.get(*index).map(key) != after.get(*index).map(key))
+        .and_then(|index| after.get(index).or_else(|| before.get(index)))
+        .map(|entry| format!("line {} {} tx {}", entry.line, entry.r#type, entry.tx))
+}
+
+/// Prints deltas as `client,currency,available,held,total,locked` rows, with
+/// a `first_divergence` column when `with_divergence` is set.
+pub async fn print_deltas(
+    deltas: &[AccountDelta],
+    with_divergence: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = tokio::io::stdout();
+    let mut header = String::from("client,currency,available,held,total,locked");
+    if with_divergence {
+        header.push_str(",first_divergence");
+    }
+    header.push('\n');
+    writer.write_all(header.as_bytes()).await?;
+
+    for delta in deltas {
+        let locked = delta
+            .locked
+            .map(|(before, after)| format!("{before}->{after}"))
+            .unwrap_or_default();
+        let mut line = format!(
+            "{},{},{:.4},{:.4},{:.4},{}",
+            delta.client,
+            delta.currency,
+            (delta.available * 10000.0).round() / 10000.0,
+            (delta.held * 10000.0).round() / 10000.0,
+            (delta.total * 10000.0).round() / 10000.0,
+            locked
+        );
+        if with_divergence {
+            line.push(',');
+            line.push_str(delta.first_divergence.as_deref().unwrap_or_default());
+        }
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
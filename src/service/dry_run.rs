@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use serde_derive::Serialize;
+
+use crate::models::account::ClientAccount;
+use crate::models::currency::Currency;
+use crate::models::ledger::LedgerAccount;
+use crate::models::transaction::TransactionType;
+use crate::service::journal::{JournalEntry, JournalOutcome};
+
+/// Records of one type by outcome.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct TypeCounts {
+    pub applied: u64,
+    pub ignored: u64,
+    pub rejected: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Rejection {
+    pub line: u64,
+    pub r#type: String,
+    pub client: u16,
+    pub tx: u32,
+    pub reason: String,
+}
+
+/// What applying a file would do, for a run whose balances are thrown away.
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRunReport {
+    pub types: BTreeMap<String, TypeCounts>,
+    pub rejections: Vec<Rejection>,
+    // Clients with an account the run locked, other than one opened for a
+    // client that was already locked.
+    pub locked: Vec<u16>,
+    // Change of the sum of every account's total, per currency, less what
+    // exchanges moved. Fees and interest move money between accounts, so only
+    // deposits, withdrawals, captures, refunds and chargebacks show up here.
+    pub net_movement: BTreeMap<Currency, f64>,
+    // Funds exchanges brought into each currency, spreads included, or took
+    // out of it when negative.
+    pub exchanged: BTreeMap<Currency, f64>,
+    // Set when a batch halted, so none of its records would be applied.
+    pub halted: bool,
+}
+
+impl DryRunReport {
+    /// Compares the accounts before and after a run whose every record went to
+    /// `entries`. A halted batch leaves the opening accounts as they were.
+    pub fn new(
+        opening: &[ClientAccount],
+        closing: &[ClientAccount],
+        entries: &[JournalEntry],
+        halted: bool,
+    ) -> Self {
+        let closing = if halted { opening } else { closing };
+        let mut types: BTreeMap<String, TypeCounts> = BTreeMap::new();
+        let mut rejections = Vec::new();
+        // Fees, spreads and other credits the engine made are journaled next to
+        // the records, under types of their own.
+        for entry in entries
+            .iter()
+            .filter(|entry| entry.r#type.parse::<TransactionType>().is_ok())
+        {
+            let counts = types.entry(entry.r#type.clone()).or_default();
+            match entry.outcome {
+                JournalOutcome::Applied => counts.applied += 1,
+                JournalOutcome::Ignored => counts.ignored += 1,
+                JournalOutcome::Rejected => {
+                    counts.rejected += 1;
+                    rejections.push(Rejection {
+                        line: entry.line,
+                        r#type: entry.r#type.clone(),
+                        client: entry.client,
+                        tx: entry.tx,
+                        reason: entry.reason.clone(),
+                    });
+                }
+            }
+        }
+        rejections.sort_by_key(|rejection| rejection.line);
+
+        let locked_before: BTreeSet<u16> = opening
+            .iter()
+            .filter(|account| account.locked)
+            .map(|account| account.client)
+            .collect();
+        let opening_locks: HashMap<(u16, &Currency), bool> = opening
+            .iter()
+            .map(|account| ((account.client, &account.currency), account.locked))
+            .collect();
+        let locked: BTreeSet<u16> = closing
+            .iter()
+            .filter(|account| {
+                account.locked
+                    && match opening_locks.get(&(account.client, &account.currency)) {
+                        Some(was_locked) => !was_locked,
+                        None => !locked_before.contains(&account.client),
+                    }
+            })
+            .map(|account| account.client)
+            .collect();
+
+        let mut net_movement: BTreeMap<Currency, f64> = BTreeMap::new();
+        let mut exchanged: BTreeMap<Currency, f64> = BTreeMap::new();
+        // The exchange ledger account gives up what exchanges credit.
+        let exchange_balance =
+            |account: &ClientAccount| account.ledger().balance(LedgerAccount::Exchange).amount();
+        for account in closing {
+            *net_movement.entry(account.currency.clone()).or_default() += account.total().amount();
+            *exchanged.entry(account.currency.clone()).or_default() -= exchange_balance(account);
+        }
+        for account in opening {
+            *net_movement.entry(account.currency.clone()).or_default() -= account.total().amount();
+            *exchanged.entry(account.currency.clone()).or_default() += exchange_balance(account);
+        }
+        for (currency, amount) in net_movement.iter_mut() {
+            *amount -= exchanged[currency];
+        }
+        if halted {
+            // Every currency the batch touched moves nothing.
+            for entry in entries {
+                net_movement.entry(entry.currency.clone()).or_default();
+            }
+        }
+        let round = |amounts: &mut BTreeMap<Currency, f64>| {
+            for amount in amounts.values_mut() {
+                // Adding 0.0 turns a rounded -0.0 into 0.0.
+                *amount = (*amount * 10000.0).round() / 10000.0 + 0.0;
+            }
+        };
+        round(&mut net_movement);
+        round(&mut exchanged);
+        exchanged.retain(|_, amount| *amount != 0.0);
+
+        DryRunReport {
+            types,
+            rejections,
+            locked: locked.into_iter().collect(),
+            net_movement,
+            exchanged,
+            halted,
+        }
+    }
+
+    /// The report as text, for a person to read.
+    pub fn to_text(&self) -> String {
+        let total = |pick: fn(&TypeCounts) -> u64| self.types.values().map(pick).sum::<u64>();
+        let (applied, ignored, rejected) = (
+            total(|counts| counts.applied),
+            total(|counts| counts.ignored),
+            total(|counts| counts.rejected),
+        );
+        let mut text = format!(
+            "Records: {} ({applied} applied, {ignored} ignored, {rejected} rejected)\n",
+            applied + ignored + rejected
+        );
+        if self.halted {
+            writeln!(text, "Batch halted: no record would be applied").ok();
+        }
+        for (r#type, counts) in &self.types {
+            writeln!(
+                text,
+                "  {type}: {} applied, {} ignored, {} rejected",
+                counts.applied, counts.ignored, counts.rejected
+            )
+            .ok();
+        }
+        if !self.rejections.is_empty() {
+            writeln!(text, "Rejections:").ok();
+        }
+        for rejection in &self.rejections {
+            writeln!(
+                text,
+                "  line {}: {} of client {}, tx {}: {}",
+                rejection.line, rejection.r#type, rejection.client, rejection.tx, rejection.reason
+            )
+            .ok();
+        }
+        let locked: Vec<String> = self.locked.iter().map(u16::to_string).collect();
+        writeln!(
+            text,
+            "Clients locked: {}",
+            if locked.is_empty() {
+                "none".to_string()
+            } else {
+                locked.join(", ")
+            }
+        )
+        .ok();
+        writeln!(text, "Net movement:").ok();
+        for (currency, amount) in &self.net_movement {
+            writeln!(text, "  {currency}: {amount:+.4}").ok();
+        }
+        if !self.exchanged.is_empty() {
+            writeln!(text, "Exchanged:").ok();
+        }
+        for (currency, amount) in &self.exchanged {
+            writeln!(text, "  {currency}: {amount:+.4}").ok();
+        }
+        text
+    }
+}
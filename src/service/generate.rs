@@ -0,0 +1,62 @@
+use std::io::Write;
+
+// SplitMix64, so the same seed always generates the same file.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    // Four decimal places, up to `max`.
+    fn amount(&mut self, max: u64) -> f64 {
+        (self.below(max * 10000) + 1) as f64 / 10000.0
+    }
+}
+
+/// Writes `records` transactions of `clients` clients to stdout in the input
+/// format, reproducible from `seed`. Most records are deposits and
+/// withdrawals; disputes act on earlier deposits of the same client, and
+/// resolves and chargebacks on open disputes.
+pub fn generate(records: u64, clients: u16, seed: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let mut rng = Rng(seed);
+    let mut writer = std::io::BufWriter::new(std::io::stdout().lock());
+    writeln!(writer, "type,client,tx,amount")?;
+
+    // (client, tx) of deposits that can be disputed, and of open disputes.
+    let mut deposits: Vec<(u16, u32)> = Vec::new();
+    let mut disputes: Vec<(u16, u32)> = Vec::new();
+    let mut next_tx: u32 = 1;
+    for _ in 0..records {
+        let roll = rng.below(100);
+        if roll >= 88 && !disputes.is_empty() {
+            let (client, tx) = disputes.swap_remove(rng.below(disputes.len() as u64) as usize);
+            let r#type = if roll < 96 { "resolve" } else { "chargeback" };
+            writeln!(writer, "{type},{client},{tx},")?;
+        } else if roll >= 80 && !deposits.is_empty() {
+            let (client, tx) = deposits.swap_remove(rng.below(deposits.len() as u64) as usize);
+            disputes.push((client, tx));
+            writeln!(writer, "dispute,{client},{tx},")?;
+        } else {
+            let client = rng.below(clients.max(1) as u64) as u16 + 1;
+            let tx = next_tx;
+            next_tx += 1;
+            if !(55..80).contains(&roll) {
+                deposits.push((client, tx));
+                writeln!(writer, "deposit,{client},{tx},{:.4}", rng.amount(1000))?;
+            } else {
+                writeln!(writer, "withdrawal,{client},{tx},{:.4}", rng.amount(500))?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::models::currency::Currency;
+use crate::models::transaction::{TransactionRecord, TransactionStatus, TransactionType};
+use crate::service::journal::JournalOutcome;
+use crate::service::transaction_engine::RecordReply;
+
+/// A processed record and its outcome, as kept by the idempotency store.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessedRecord {
+    pub r#type: TransactionType,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<f64>,
+    pub dispute: Option<u32>,
+    pub currency: Option<Currency>,
+    pub to_currency: Option<Currency>,
+    pub outcome: JournalOutcome,
+    pub reason: String,
+    pub fee: f64,
+    // Disputes raised on `tx` when a resolve or chargeback without a dispute
+    // number was processed.
+    #[serde(default)]
+    pub disputes_raised: Option<u32>,
+}
+
+impl ProcessedRecord {
+    fn key(&self) -> RecordKey {
+        RecordKey::new(
+            &self.r#type,
+            self.client,
+            self.tx,
+            self.dispute,
+            self.amount,
+            self.disputes_raised,
+        )
+    }
+
+    fn matches(&self, record: &TransactionRecord) -> bool {
+        self.r#type == record.r#type
+            && self.client == record.client
+            && self.amount == record.amount
+            && self.dispute == record.dispute
+            && self.currency == record.currency
+            && self.to_currency == record.to_currency
+    }
+}
+
+/// What a record is recognized by when it is resubmitted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RecordKey {
+    kind: KeyKind,
+    // Disputes raised on the tx, for a resolve or chargeback without a
+    // dispute number.
+    disputes_raised: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum KeyKind {
+    // A record introducing its tx id; any other record with the id conflicts.
+    Origin(u32),
+    // A record acting on an earlier transaction once, so a different record
+    // with the same key conflicts: (type, client, tx, dispute).
+    Reference(TransactionType, u16, u32, Option<u32>),
+    // A partial dispute, capture or refund, which can be repeated with other
+    // amounts: (type, client, tx, amount bits).
+    Partial(TransactionType, u16, u32, Option<u64>),
+}
+
+impl RecordKey {
+    fn new(
+        r#type: &TransactionType,
+        client: u16,
+        tx: u32,
+        dispute: Option<u32>,
+        amount: Option<f64>,
+        disputes_raised: Option<u32>,
+    ) -> Self {
+        let kind = match r#type {
+            TransactionType::Dispute | TransactionType::Capture | TransactionType::Refund => {
+                KeyKind::Partial(r#type.clone(), client, tx, amount.map(f64::to_bits))
+            }
+            TransactionType::Resolve | TransactionType::Chargeback | TransactionType::Release => {
+                KeyKind::Reference(r#type.clone(), client, tx, dispute)
+            }
+            _ => KeyKind::Origin(tx),
+        };
+        RecordKey {
+            kind,
+            disputes_raised,
+        }
+    }
+
+    /// Key of `record` given the transactions processed before it. A resolve
+    /// or chargeback without a dispute number only repeats an earlier one
+    /// when no dispute was raised on the transaction since.
+    pub fn of(
+        record: &TransactionRecord,
+        processed_transactions: &HashMap<u32, TransactionStatus>,
+    ) -> Self {
+        let disputes_raised = match record.r#type {
+            TransactionType::Resolve | TransactionType::Chargeback if record.dispute.is_none() => {
+                processed_transactions
+                    .get(&record.tx)
+                    .map(|status| status.disputes.len() as u32)
+            }
+            _ => None,
+        };
+        RecordKey::new(
+            &record.r#type,
+            record.client,
+            record.tx,
+            record.dispute,
+            record.amount,
+            disputes_raised,
+        )
+    }
+}
+
+/// Outcomes of every processed record, so a resubmitted record is answered
+/// with its first outcome instead of being applied again. Kept in memory, or
+/// appended to a CSV file that is read back on the next run.
+#[derive(Debug, Default)]
+pub struct IdempotencyStore {
+    records: HashMap<RecordKey, ProcessedRecord>,
+    writer: Option<csv::Writer<std::fs::File>>,
+}
+
+impl IdempotencyStore {
+    pub fn in_memory() -> Self {
+        IdempotencyStore::default()
+    }
+
+    /// Opens the store at `file_path` with every record it already holds,
+    /// creating it when missing.
+    pub fn open(file_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(file_path)?;
+        let empty = file.metadata()?.len() == 0;
+
+        let mut store = IdempotencyStore::default();
+        for record in csv::Reader::from_path(file_path)?.deserialize() {
+            let record: ProcessedRecord = record?;
+            store.records.insert(record.key(), record);
+        }
+        store.writer = Some(
+            csv::WriterBuilder::new()
+                .has_headers(empty)
+                .from_writer(file),
+        );
+        Ok(store)
+    }
+
+    /// The records kept at `file_path`, without writing new ones back; a
+    /// missing file is an empty store.
+    pub fn load(file_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut store = IdempotencyStore::default();
+        if std::path::Path::new(file_path).exists() {
+            for record in csv::Reader::from_path(file_path)?.deserialize() {
+                let record: ProcessedRecord = record?;
+                store.records.insert(record.key(), record);
+            }
+        }
+        Ok(store)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// The first outcome of `record`, keyed by `key`, when it was processed
+    /// before. A different record with the same key is a `TransactionConflict`.
+    pub fn check(
+        &self,
+        key: &RecordKey,
+        record: &TransactionRecord,
+    ) -> Result<Option<&ProcessedRecord>, AppError> {
+        match self.records.get(key) {
+            Some(earlier) if earlier.matches(record) => Ok(Some(earlier)),
+            Some(_) => Err(AppError::TransactionConflict(record.tx)),
+            None => Ok(None),
+        }
+    }
+
+    /// Keeps the outcome of a newly processed record, writing it through to
+    /// the file so it survives a crash.
+    pub fn remember(
+        &mut self,
+        key: RecordKey,
+        record: &TransactionRecord,
+        reply: &RecordReply,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let processed = ProcessedRecord {
+            r#type: record.r#type.clone(),
+            client: record.client,
+            tx: record.tx,
+            amount: record.amount,
+            dispute: record.dispute,
+            currency: record.currency.clone(),
+            to_currency: record.to_currency.clone(),
+            outcome: reply.outcome,
+            reason: reply.reason.clone().unwrap_or_default(),
+            fee: reply.fee,
+            disputes_raised: key.disputes_raised,
+        };
+        if let Some(writer) = &mut self.writer {
+            writer.serialize(&processed)?;
+            writer.flush()?;
+        }
+        self.records.insert(key, processed);
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::models::account::ClientAccount;
+use crate::models::currency::Currency;
+use crate::models::transaction::TransactionRecord;
+
+/// One line of the journal: a processed record, or a fee credited to the house
+/// account, with the account balances right after it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JournalEntry {
+    // 1-based position of the triggering record in the input.
+    pub line: u64,
+    pub r#type: String,
+    pub client: u16,
+    pub tx: u32,
+    pub currency: Currency,
+    pub amount: Option<f64>,
+    pub timestamp: Option<u64>,
+    pub fee: f64,
+    pub outcome: JournalOutcome,
+    pub reason: String,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalOutcome {
+    Applied,
+    Ignored,
+    Rejected,
+}
+
+impl std::fmt::Display for JournalOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JournalOutcome::Applied => write!(f, "applied"),
+            JournalOutcome::Ignored => write!(f, "ignored"),
+            JournalOutcome::Rejected => write!(f, "rejected"),
+        }
+    }
+}
+
+impl JournalEntry {
+    pub fn for_record(
+        line: u64,
+        record: &TransactionRecord,
+        fee: f64,
+        outcome: JournalOutcome,
+        reason: String,
+        account: &ClientAccount,
+    ) -> Self {
+        JournalEntry {
+            line,
+            r#type: record.r#type.to_string(),
+            client: record.client,
+            tx: record.tx,
+            currency: account.currency.clone(),
+            amount: record.amount,
+            timestamp: record.timestamp,
+            fee,
+            outcome,
+            reason,
+            available: account.available().amount(),
+            held: account.held().amount(),
+            total: account.total().amount(),
+            locked: account.locked,
+        }
+    }
+
+    pub fn for_fee(line: u64, record: &TransactionRecord, fee: f64, house: &ClientAccount) -> Self {
+        JournalEntry::for_credit(line, "fee", record, record.tx, fee, house)
+    }
+
+    /// A credit the engine made as a side effect of the record on `line`, such
+    /// as a fee, an exchange spread, or the target leg of an exchange.
+    pub fn for_credit(
+        line: u64,
+        r#type: &str,
+        record: &TransactionRecord,
+        tx: u32,
+        amount: f64,
+        account: &ClientAccount,
+    ) -> Self {
+        JournalEntry {
+            line,
+            r#type: r#type.to_string(),
+            client: account.client,
+            tx,
+            currency: account.currency.clone(),
+            amount: Some(amount),
+            timestamp: record.timestamp,
+            fee: 0.0,
+            outcome: JournalOutcome::Applied,
+            reason: String::new(),
+            available: account.available().amount(),
+            held: account.held().amount(),
+            total: account.total().amount(),
+            locked: account.locked,
+        }
+    }
+}
+
+/// Append-only log of every record the engine processed, written to a CSV
+/// file or kept in memory.
+#[derive(Debug)]
+pub enum Journal {
+    File(Box<csv::Writer<std::fs::File>>),
+    Memory(Vec<JournalEntry>),
+}
+
+impl Journal {
+    pub fn create(file_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Journal::File(Box::new(csv::Writer::from_path(file_path)?)))
+    }
+
+    pub fn in_memory() -> Self {
+        Journal::Memory(Vec::new())
+    }
+
+    /// Reads back a journal file written by `Journal::create`.
+    pub fn read(file_path: &str) -> Result<Vec<JournalEntry>, Box<dyn std::error::Error>> {
+        let mut rdr = csv::Reader::from_path(file_path)?;
+        let mut entries = Vec::new();
+        for entry in rdr.deserialize() {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+
+    pub fn record(&mut self, entry: &JournalEntry) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Journal::File(writer) => writer.serialize(entry)?,
+            Journal::Memory(entries) => entries.push(entry.clone()),
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Journal::File(writer) = self {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Entries recorded so far; always empty for a file journal.
+    pub fn entries(&self) -> &[JournalEntry] {
+        match self {
+            Journal::File(_) => &[],
+            Journal::Memory(entries) => entries,
+        }
+    }
+}
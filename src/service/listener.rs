@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::models::transaction::TransactionRecord;
+use crate::service::journal::JournalOutcome;
+use crate::service::transaction_engine::{RecordReply, TransactionEngineService};
+
+// Acknowledgement of a row, queued in the order the rows arrived.
+enum Ack {
+    Pending(oneshot::Receiver<RecordReply>),
+    // Line number of a row that could not be parsed, and why.
+    Malformed(u64, String),
+}
+
+/// Accepts CSV streams on `addr` until the process ends. Each connection
+/// sends rows in the input file format, starting with the header, and gets
+/// one `line,accepted` or `line,rejected,reason` acknowledgement back per
+/// row, in order. Lines are numbered per connection.
+pub async fn listen(
+    engine: TransactionEngineService,
+    addr: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(addr).await?;
+    println!("Listening on {}", listener.local_addr()?);
+
+    let engine = Arc::new(engine);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let engine = engine.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(&engine, stream).await {
+                tracing::warn!(%peer, error = %err, "connection failed");
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    engine: &TransactionEngineService,
+    stream: TcpStream,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (reader, mut writer) = stream.into_split();
+
+    // Acks are written by a separate task so the reader keeps submitting rows
+    // while earlier ones are still being applied.
+    let (acks, mut pending) = mpsc::channel::<Ack>(1024);
+    let ack_writer = tokio::spawn(async move {
+        while let Some(ack) = pending.recv().await {
+            let ack = match ack {
+                Ack::Pending(receiver) => match receiver.await {
+                    Ok(reply) => format_reply(&reply),
+                    Err(_) => break,
+                },
+                Ack::Malformed(line, reason) => format!("{line},rejected,{reason}\n"),
+            };
+            if writer.write_all(ack.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+        writer.flush().await.ok();
+    });
+
+    let reader = tokio_util::compat::TokioAsyncReadCompatExt::compat(reader);
+    let mut rdr = csv_async::AsyncReaderBuilder::new()
+        .trim(csv_async::Trim::All)
+        .create_deserializer(reader);
+    let mut records = rdr.deserialize::<TransactionRecord>();
+
+    let mut line = 0;
+    while let Some(result) = records.next().await {
+        line += 1;
+        let ack = match result {
+            Ok(record) => Ack::Pending(engine.submit(line, record).await?),
+            Err(err) => Ack::Malformed(line, err.to_string()),
+        };
+        // A closed channel means the peer stopped reading acks.
+        if acks.send(ack).await.is_err() {
+            break;
+        }
+    }
+
+    drop(acks);
+    ack_writer.await?;
+    Ok(())
+}
+
+fn format_reply(reply: &RecordReply) -> String {
+    match (&reply.outcome, &reply.reason) {
+        (JournalOutcome::Rejected, Some(reason)) => format!("{},rejected,{reason}\n", reply.line),
+        (JournalOutcome::Rejected, None) => format!("{},rejected\n", reply.line),
+        _ => format!("{},accepted\n", reply.line),
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use serde_derive::Serialize;
+
+use crate::error::AppError;
+use crate::models::transaction::{TransactionOutcome, TransactionRecord, TransactionType};
+
+/// Counters of the records the engine processed, shared by its workers.
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    applied: AtomicU64,
+    ignored: AtomicU64,
+    rejected: AtomicU64,
+    disputes: AtomicU64,
+    chargebacks: AtomicU64,
+    // Most records seen waiting in one client worker's channel.
+    peak_queue_depth: AtomicU64,
+    // Rejected records by error name.
+    rejections: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            started: Instant::now(),
+            applied: AtomicU64::new(0),
+            ignored: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            disputes: AtomicU64::new(0),
+            chargebacks: AtomicU64::new(0),
+            peak_queue_depth: AtomicU64::new(0),
+            rejections: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+/// The metrics at one point of a run.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    pub records: u64,
+    pub applied: u64,
+    pub ignored: u64,
+    pub rejected: u64,
+    pub rejections: BTreeMap<String, u64>,
+    // Applied disputes and chargebacks.
+    pub disputes: u64,
+    pub chargebacks: u64,
+    pub elapsed_secs: f64,
+    pub records_per_sec: f64,
+    pub peak_queue_depth: u64,
+}
+
+impl Metrics {
+    /// Counts a processed record by its outcome.
+    pub fn record(
+        &self,
+        record: &TransactionRecord,
+        result: &Result<TransactionOutcome, Box<dyn std::error::Error>>,
+    ) {
+        match result {
+            Ok(outcome) if outcome.applied => {
+                self.applied.fetch_add(1, Ordering::Relaxed);
+                if record.r#type == TransactionType::Dispute {
+                    self.disputes.fetch_add(1, Ordering::Relaxed);
+                } else if record.r#type == TransactionType::Chargeback {
+                    self.chargebacks.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Ok(_) => {
+                self.ignored.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(err) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                let reason = err
+                    .downcast_ref::<AppError>()
+                    .map_or("Other", AppError::name);
+                *self
+                    .rejections
+                    .lock()
+                    .unwrap()
+                    .entry(reason.to_string())
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    /// Records how many records wait in a worker's channel.
+    pub fn observe_queue_depth(&self, depth: usize) {
+        self.peak_queue_depth
+            .fetch_max(depth as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let applied = self.applied.load(Ordering::Relaxed);
+        let ignored = self.ignored.load(Ordering::Relaxed);
+        let rejected = self.rejected.load(Ordering::Relaxed);
+        let records = applied + ignored + rejected;
+        let elapsed_secs = self.started.elapsed().as_secs_f64();
+        MetricsSnapshot {
+            records,
+            applied,
+            ignored,
+            rejected,
+            rejections: self.rejections.lock().unwrap().clone(),
+            disputes: self.disputes.load(Ordering::Relaxed),
+            chargebacks: self.chargebacks.load(Ordering::Relaxed),
+            elapsed_secs,
+            records_per_sec: if elapsed_secs > 0.0 {
+                records as f64 / elapsed_secs
+            } else {
+                0.0
+            },
+            peak_queue_depth: self.peak_queue_depth.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    /// The metrics in the Prometheus text format, with `queue_depth` records
+    /// currently waiting for the workers.
+    pub fn to_prometheus(&self, queue_depth: u64) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            writeln!(text, "# HELP transaction_engine_{name} {help}").ok();
+            writeln!(text, "# TYPE transaction_engine_{name} {kind}").ok();
+            for (labels, value) in samples {
+                writeln!(text, "transaction_engine_{name}{labels} {value}").ok();
+            }
+        };
+
+        metric(
+            "records_total",
+            "counter",
+            "Records processed, by outcome.",
+            [
+                ("applied", self.applied),
+                ("ignored", self.ignored),
+                ("rejected", self.rejected),
+            ]
+            .into_iter()
+            .map(|(outcome, count)| (format!("{{outcome=\"{outcome}\"}}"), count.to_string()))
+            .collect(),
+        );
+        metric(
+            "rejections_total",
+            "counter",
+            "Rejected records, by reason.",
+            self.rejections
+                .iter()
+                .map(|(reason, count)| (format!("{{reason=\"{reason}\"}}"), count.to_string()))
+                .collect(),
+        );
+        metric(
+            "disputes_total",
+            "counter",
+            "Disputes applied.",
+            vec![(String::new(), self.disputes.to_string())],
+        );
+        metric(
+            "chargebacks_total",
+            "counter",
+            "Chargebacks applied.",
+            vec![(String::new(), self.chargebacks.to_string())],
+        );
+        metric(
+            "records_per_second",
+            "gauge",
+            "Records processed per second since the engine started.",
+            vec![(String::new(), format!("{:.2}", self.records_per_sec))],
+        );
+        metric(
+            "queue_depth",
+            "gauge",
+            "Records waiting for the client workers.",
+            vec![(String::new(), queue_depth.to_string())],
+        );
+        metric(
+            "queue_depth_peak",
+            "gauge",
+            "Most records seen waiting for one client worker.",
+            vec![(String::new(), self.peak_queue_depth.to_string())],
+        );
+        text
+    }
+
+    /// A few lines summing up a run, for stderr.
+    pub fn summary(&self) -> String {
+        let mut text = format!(
+            "Processed {} records in {:.3}s ({:.0} records/s): {} applied, {} ignored, {} rejected\n",
+            self.records,
+            self.elapsed_secs,
+            self.records_per_sec,
+            self.applied,
+            self.ignored,
+            self.rejected
+        );
+        for (reason, count) in &self.rejections {
+            writeln!(text, "  rejected {reason}: {count}").ok();
+        }
+        writeln!(
+            text,
+            "Disputes: {}, chargebacks: {}, peak queue depth: {}",
+            self.disputes, self.chargebacks, self.peak_queue_depth
+        )
+        .ok();
+        text
+    }
+}
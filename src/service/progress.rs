@@ -0,0 +1,121 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::task::JoinHandle;
+
+use crate::service::metrics::Metrics;
+
+/// How far the reader got through a file. The reader loop only bumps these
+/// counters; a separate task reports them.
+#[derive(Debug)]
+pub struct Progress {
+    started: Instant,
+    total_bytes: u64,
+    bytes_read: AtomicU64,
+    clients: AtomicU64,
+}
+
+impl Progress {
+    pub fn new(total_bytes: u64) -> Self {
+        Progress {
+            started: Instant::now(),
+            total_bytes,
+            bytes_read: AtomicU64::new(0),
+            clients: AtomicU64::new(0),
+        }
+    }
+
+    pub fn add_client(&self) {
+        self.clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A line such as `Progress: 1.5 MiB of 3.0 MiB (50.0%), 120000 records/s,
+    /// 42 clients, 7 rejected`.
+    pub fn report(&self, metrics: &Metrics) -> String {
+        let bytes_read = self.bytes_read.load(Ordering::Relaxed);
+        let percent = if self.total_bytes > 0 {
+            bytes_read as f64 * 100.0 / self.total_bytes as f64
+        } else {
+            100.0
+        };
+        let snapshot = metrics.snapshot();
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let records_per_sec = if elapsed > 0.0 {
+            snapshot.records as f64 / elapsed
+        } else {
+            0.0
+        };
+        format!(
+            "Progress: {} of {} ({percent:.1}%), {records_per_sec:.0} records/s, {} clients, {} rejected",
+            format_bytes(bytes_read),
+            format_bytes(self.total_bytes),
+            self.clients.load(Ordering::Relaxed),
+            snapshot.rejected
+        )
+    }
+
+    /// Prints a report on stderr every `interval` until the task is aborted.
+    pub fn spawn_reporter(
+        self: &Arc<Self>,
+        metrics: Arc<Metrics>,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let progress = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately.
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                eprintln!("{}", progress.report(&metrics));
+            }
+        })
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Counts the bytes read through it into a `Progress`.
+pub struct CountingReader<R> {
+    inner: R,
+    progress: Arc<Progress>,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R, progress: Arc<Progress>) -> Self {
+        CountingReader { inner, progress }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.progress
+                .bytes_read
+                .fetch_add((buf.filled().len() - before) as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+}
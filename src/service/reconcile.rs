@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+
+use tokio::io::AsyncWriteExt;
+
+use crate::models::account::AccountBalances;
+use crate::models::currency::Currency;
+
+/// A difference between the engine's accounts and the expected balances.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Break {
+    pub client: u16,
+    pub currency: Currency,
+    pub kind: BreakKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BreakKind {
+    // A balance differs: (field, expected, actual).
+    Balance(&'static str, f64, f64),
+    Locked { expected: bool, actual: bool },
+    // Expected but not produced by the engine.
+    Missing,
+    // Produced by the engine but not expected.
+    Extra,
+}
+
+/// Reads accounts in the `client,[currency,]available,held,total,locked`
+/// format the engine prints. Without a currency column `default_currency` is
+/// assumed.
+pub fn read_accounts(
+    file_path: &str,
+    default_currency: &Currency,
+) -> Result<Vec<AccountBalances>, Box<dyn std::error::Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(file_path)?;
+    let has_currency = rdr.headers()?.iter().any(|header| header == "currency");
+    let mut accounts = Vec::new();
+    for account in rdr.deserialize() {
+        let mut account: AccountBalances = account?;
+        if !has_currency {
+            account.currency = default_currency.clone();
+        }
+        accounts.push(account);
+    }
+    Ok(accounts)
+}
+
+/// Breaks between `actual` and `expected` accounts, ordered by client and
+/// currency. Balances are compared at four decimal places.
+pub fn reconcile(actual: &[AccountBalances], expected: &[AccountBalances]) -> Vec<Break> {
+    let by_key = |accounts: &[AccountBalances]| {
+        accounts
+            .iter()
+            .map(|account| ((account.client, account.currency.clone()), account.clone()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let actual = by_key(actual);
+    let expected = by_key(expected);
+    let round = |amount: f64| (amount * 10000.0).round() / 10000.0;
+
+    let mut keys: Vec<&(u16, Currency)> = actual.keys().chain(expected.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut breaks = Vec::new();
+    for key in keys {
+        let (client, currency) = key.clone();
+        let kinds = match (expected.get(key), actual.get(key)) {
+            (Some(expected), Some(actual)) => {
+                let mut kinds: Vec<BreakKind> = [
+                    ("available", expected.available, actual.available),
+                    ("held", expected.held, actual.held),
+                    ("total", expected.total, actual.total),
+                ]
+                .into_iter()
+                .filter(|(_, expected, actual)| round(*expected) != round(*actual))
+                .map(|(field, expected, actual)| {
+                    BreakKind::Balance(field, round(expected), round(actual))
+                })
+                .collect();
+                if expected.locked != actual.locked {
+                    kinds.push(BreakKind::Locked {
+                        expected: expected.locked,
+                        actual: actual.locked,
+                    });
+                }
+                kinds
+            }
+            (Some(_), None) => vec![BreakKind::Missing],
+            (None, Some(_)) => vec![BreakKind::Extra],
+            (None, None) => Vec::new(),
+        };
+        breaks.extend(kinds.into_iter().map(|kind| Break {
+            client,
+            currency: currency.clone(),
+            kind,
+        }));
+    }
+    breaks
+}
+
+/// Prints breaks as `client,currency,issue,expected,actual,difference` rows.
+pub async fn print_breaks(breaks: &[Break]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = tokio::io::stdout();
+    writer
+        .write_all(b"client,currency,issue,expected,actual,difference\n")
+        .await?;
+    for b in breaks {
+        let details = match &b.kind {
+            BreakKind::Balance(field, expected, actual) => format!(
+                "{field},{expected:.4},{actual:.4},{:.4}",
+                ((actual - expected) * 10000.0).round() / 10000.0
+            ),
+            BreakKind::Locked { expected, actual } => format!("locked,{expected},{actual},"),
+            BreakKind::Missing => "missing,,,".to_string(),
+            BreakKind::Extra => "extra,,,".to_string(),
+        };
+        let line = format!("{},{},{}\n", b.client, b.currency, details);
+        writer.write_all(line.as_bytes()).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
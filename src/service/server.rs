@@ -0,0 +1,174 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    routing::{get, post},
+};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::models::account::AccountBalances;
+use crate::models::currency::Currency;
+use crate::models::transaction::{Dispute, TransactionRecord};
+use crate::service::transaction_engine::{RecordReply, TransactionEngineService};
+
+struct ServerState {
+    engine: TransactionEngineService,
+    // Records are numbered in the order they were submitted, like input lines.
+    next_line: AtomicU64,
+}
+
+type ApiError = (StatusCode, String);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Submission {
+    One(TransactionRecord),
+    Batch(Vec<TransactionRecord>),
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum SubmissionReply {
+    One(RecordReply),
+    Batch(Vec<RecordReply>),
+}
+
+/// Dispute state of a deposit or authorization.
+#[derive(Serialize)]
+struct TransactionView {
+    tx: u32,
+    client: u16,
+    r#type: String,
+    amount: Option<f64>,
+    currency: Option<Currency>,
+    disputed: f64,
+    charged_back: f64,
+    refunded: f64,
+    disputes: Vec<Dispute>,
+}
+
+/// Serves the engine over HTTP on `addr` until the process ends:
+///
+/// - `POST /transactions`: apply a record, or an array of records, and return
+///   the outcome of each
+/// - `GET /accounts`: every account
+/// - `GET /accounts/{client}`: the client's account in each currency
+/// - `GET /transactions/{tx}`: dispute status of a deposit or authorization
+/// - `GET /metrics`: the engine's metrics in the Prometheus text format
+pub async fn serve(
+    engine: TransactionEngineService,
+    addr: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    println!("Listening on {}", listener.local_addr()?);
+
+    let state = Arc::new(ServerState {
+        engine,
+        next_line: AtomicU64::new(1),
+    });
+    let app = Router::new()
+        .route("/transactions", post(post_transactions))
+        .route("/transactions/{tx}", get(get_transaction))
+        .route("/accounts", get(get_accounts))
+        .route("/accounts/{client}", get(get_account))
+        .route("/metrics", get(get_metrics))
+        .with_state(state);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn post_transactions(
+    State(state): State<Arc<ServerState>>,
+    Json(submission): Json<Submission>,
+) -> Result<Json<SubmissionReply>, ApiError> {
+    let (records, batch) = match submission {
+        Submission::One(record) => (vec![record], false),
+        Submission::Batch(records) => (records, true),
+    };
+
+    // Submit the whole batch before waiting, so each client's records are
+    // applied in order while different clients proceed in parallel.
+    let mut receivers = Vec::new();
+    for record in records {
+        let line = state.next_line.fetch_add(1, Ordering::SeqCst);
+        let receiver = state
+            .engine
+            .submit(line, record)
+            .await
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+        receivers.push(receiver);
+    }
+    let mut replies = Vec::new();
+    for receiver in receivers {
+        replies.push(
+            receiver
+                .await
+                .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?,
+        );
+    }
+
+    match (batch, replies.pop()) {
+        (false, Some(reply)) => Ok(Json(SubmissionReply::One(reply))),
+        (_, last) => {
+            replies.extend(last);
+            Ok(Json(SubmissionReply::Batch(replies)))
+        }
+    }
+}
+
+async fn get_accounts(State(state): State<Arc<ServerState>>) -> Json<Vec<AccountBalances>> {
+    Json(state.engine.balances())
+}
+
+async fn get_metrics(
+    State(state): State<Arc<ServerState>>,
+) -> ([(header::HeaderName, &'static str); 1], String) {
+    let queue_depth = state.engine.queue_depth().await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.engine.metrics.snapshot().to_prometheus(queue_depth),
+    )
+}
+
+async fn get_account(
+    State(state): State<Arc<ServerState>>,
+    Path(client): Path<u16>,
+) -> Result<Json<Vec<AccountBalances>>, ApiError> {
+    let accounts: Vec<AccountBalances> = state
+        .engine
+        .balances()
+        .into_iter()
+        .filter(|account| account.client == client)
+        .collect();
+    if accounts.is_empty() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("No account for client {client}"),
+        ));
+    }
+    Ok(Json(accounts))
+}
+
+async fn get_transaction(
+    State(state): State<Arc<ServerState>>,
+    Path(tx): Path<u32>,
+) -> Result<Json<TransactionView>, ApiError> {
+    let status = state
+        .engine
+        .transaction_status(tx)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("No transaction {tx}")))?;
+    Ok(Json(TransactionView {
+        tx,
+        client: status.record.client,
+        r#type: status.record.r#type.to_string(),
+        amount: status.record.amount,
+        currency: status.record.currency.clone(),
+        disputed: status.disputed_amount(),
+        charged_back: status.charged_back_amount(),
+        refunded: status.refunded,
+        disputes: status.disputes,
+    }))
+}
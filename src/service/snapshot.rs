@@ -0,0 +1,388 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::models::account::{AccountBalances, ClientAccount};
+use crate::models::currency::Currency;
+use crate::models::money::Money;
+use crate::models::transaction::{
+    Dispute, DisputeState, TransactionRecord, TransactionStatus, TransactionType,
+};
+use crate::service::reconcile;
+
+/// A deposit of an earlier run that later records may still dispute, refund,
+/// resolve or charge back, an open dispute of such a deposit, or an
+/// authorization later records may still capture or release.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedTransaction {
+    #[serde(default = "deposit")]
+    pub r#type: TransactionType,
+    pub client: u16,
+    pub tx: u32,
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    // What is left of the deposit after chargebacks and refunds, of the
+    // authorization hold after captures, or the amount of the dispute.
+    pub amount: f64,
+    // Part of the deposit's `amount` under open disputes.
+    #[serde(default)]
+    pub disputed: f64,
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    // Disputes raised against the deposit, open or not, so later ones keep
+    // their numbers.
+    #[serde(default)]
+    pub disputes_raised: u32,
+    // Dispute rows only: the number `resolve` and `chargeback` records select
+    // the dispute by, and the parts of its amount held and not covered.
+    #[serde(default)]
+    pub dispute: Option<u32>,
+    #[serde(default)]
+    pub held: Option<f64>,
+    #[serde(default)]
+    pub shortfall: f64,
+}
+
+// Index rows without a type, as older runs wrote them, are deposits.
+fn deposit() -> TransactionType {
+    TransactionType::Deposit
+}
+
+/// Account state an engine starts from instead of empty accounts.
+#[derive(Debug, Default, Clone)]
+pub struct Snapshot {
+    pub accounts: Vec<ClientAccount>,
+    pub transactions: HashMap<u32, TransactionStatus>,
+}
+
+impl Snapshot {
+    /// Reads the opening balances from `accounts_path`, in the format the engine
+    /// prints, and the deposits and authorizations later records may act on
+    /// from `transactions_path`.
+    pub fn from_files(
+        accounts_path: &str,
+        transactions_path: Option<&str>,
+        default_currency: &Currency,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let index = match transactions_path {
+            Some(path) => {
+                let mut rdr = csv::ReaderBuilder::new()
+                    .trim(csv::Trim::All)
+                    .from_path(path)?;
+                rdr.deserialize()
+                    .collect::<Result<Vec<IndexedTransaction>, _>>()?
+            }
+            None => Vec::new(),
+        };
+        Ok(Snapshot::new(
+            reconcile::read_accounts(accounts_path, default_currency)?,
+            index,
+            default_currency,
+        )?)
+    }
+
+    /// Checks that every account's `total` is its `available` plus `held`, that
+    /// its `held` is the held part of the open disputes of its deposits plus its
+    /// open authorizations, and that its `shortfall` covers the open disputes'.
+    pub fn new(
+        opening: Vec<AccountBalances>,
+        index: Vec<IndexedTransaction>,
+        default_currency: &Currency,
+    ) -> Result<Self, AppError> {
+        let round = |amount: f64| (amount * 10000.0).round() / 10000.0;
+        let currency_of = |indexed: &IndexedTransaction| {
+            indexed
+                .currency
+                .clone()
+                .unwrap_or_else(|| default_currency.clone())
+        };
+
+        let (dispute_rows, index): (Vec<_>, Vec<_>) = index
+            .into_iter()
+            .partition(|indexed| indexed.r#type == TransactionType::Dispute);
+        // Key: (client, currency), Value: open authorizations and their total
+        let mut authorized: BTreeMap<(u16, Currency), (Vec<u32>, f64)> = BTreeMap::new();
+        let mut transactions = HashMap::new();
+        // Key: tx, Value: the deposit's disputed amount and disputes raised
+        let mut deposits: HashMap<u32, (f64, u32)> = HashMap::new();
+        for indexed in index {
+            let currency = currency_of(&indexed);
+            if !indexed.amount.is_finite()
+                || indexed.amount < 0.0
+                || !(0.0..=indexed.amount).contains(&indexed.disputed)
+            {
+                return Err(AppError::InvalidSnapshot(format!(
+                    "tx {} disputes {} of {}",
+                    indexed.tx, indexed.disputed, indexed.amount
+                )));
+            }
+            match indexed.r#type {
+                TransactionType::Deposit => {
+                    deposits.insert(indexed.tx, (indexed.disputed, indexed.disputes_raised));
+                }
+                TransactionType::Authorize if indexed.disputed == 0.0 => {
+                    let (txs, total) = authorized
+                        .entry((indexed.client, currency.clone()))
+                        .or_default();
+                    txs.push(indexed.tx);
+                    *total += indexed.amount;
+                }
+                _ => {
+                    return Err(AppError::InvalidSnapshot(format!(
+                        "tx {} is not a deposit, a dispute or an undisputed authorization",
+                        indexed.tx
+                    )));
+                }
+            }
+
+            let status = TransactionStatus::new(TransactionRecord {
+                r#type: indexed.r#type.clone(),
+                client: indexed.client,
+                tx: indexed.tx,
+                amount: Some(indexed.amount),
+                dispute: None,
+                currency: Some(currency),
+                to_currency: None,
+                timestamp: indexed.timestamp,
+            });
+            if transactions.insert(indexed.tx, status).is_some() {
+                return Err(AppError::InvalidSnapshot(format!(
+                    "tx {} is indexed twice",
+                    indexed.tx
+                )));
+            }
+        }
+
+        // Disputes that are no longer open only keep the numbering.
+        let closed = Dispute {
+            amount: 0.0,
+            held: 0.0,
+            shortfall: 0.0,
+            state: DisputeState::Resolved,
+        };
+        for indexed in dispute_rows {
+            let tx = indexed.tx;
+            let held = indexed.held.unwrap_or(indexed.amount);
+            let (status, number) = match (transactions.get_mut(&tx), deposits.get(&tx)) {
+                (Some(status), Some((_, raised)))
+                    if status.record.client == indexed.client
+                        && status.record.currency == Some(currency_of(&indexed)) =>
+                {
+                    match indexed.dispute {
+                        Some(number) if (1..=*raised).contains(&number) => (status, number),
+                        _ => {
+                            return Err(AppError::InvalidSnapshot(format!(
+                                "dispute {:?} of tx {tx} is not one of its {raised} disputes",
+                                indexed.dispute
+                            )));
+                        }
+                    }
+                }
+                _ => {
+                    return Err(AppError::InvalidSnapshot(format!(
+                        "dispute of tx {tx} has no indexed deposit of client {}",
+                        indexed.client
+                    )));
+                }
+            };
+            if !indexed.amount.is_finite()
+                || indexed.amount <= 0.0
+                || !(0.0..=indexed.amount).contains(&held)
+                || !(0.0..=indexed.amount).contains(&indexed.shortfall)
+            {
+                return Err(AppError::InvalidSnapshot(format!(
+                    "dispute {number} of tx {tx} holds {held} with a shortfall of {} of {}",
+                    indexed.shortfall, indexed.amount
+                )));
+            }
+            let number = number as usize;
+            if status.disputes.len() < number {
+                status.disputes.resize(number, closed.clone());
+            }
+            if status.disputes[number - 1].state == DisputeState::Open {
+                return Err(AppError::InvalidSnapshot(format!(
+                    "dispute {number} of tx {tx} is indexed twice"
+                )));
+            }
+            status.disputes[number - 1] = Dispute {
+                amount: indexed.amount,
+                held,
+                shortfall: indexed.shortfall,
+                state: DisputeState::Open,
+            };
+        }
+
+        // Key: (client, currency), Value: held and shortfall of open disputes
+        let mut disputed: BTreeMap<(u16, Currency), (f64, f64)> = BTreeMap::new();
+        for (tx, (amount, raised)) in deposits {
+            let status = transactions.get_mut(&tx).expect("deposits are indexed");
+            // Older indexes only carry the disputed amount, held in full.
+            if status.disputes.is_empty() && amount > 0.0 {
+                status
+                    .disputes
+                    .resize(raised.saturating_sub(1) as usize, closed.clone());
+                status.disputes.push(Dispute {
+                    amount,
+                    held: amount,
+                    shortfall: 0.0,
+                    state: DisputeState::Open,
+                });
+            }
+            if round(status.disputed_amount()) != round(amount) {
+                return Err(AppError::InvalidSnapshot(format!(
+                    "tx {tx} disputes {amount} but its open disputes are {}",
+                    status.disputed_amount()
+                )));
+            }
+            if status.disputes.len() < raised as usize {
+                status.disputes.resize(raised as usize, closed.clone());
+            }
+            let currency = status
+                .record
+                .currency
+                .clone()
+                .expect("indexed with a currency");
+            let (held, shortfall) = disputed
+                .entry((status.record.client, currency))
+                .or_default();
+            for dispute in &status.disputes {
+                if dispute.state == DisputeState::Open {
+                    *held += dispute.held;
+                    *shortfall += dispute.shortfall;
+                }
+            }
+        }
+
+        let mut accounts = Vec::new();
+        for account in opening {
+            let (client, currency) = (account.client, account.currency.clone());
+            if [account.available, account.held, account.total]
+                .iter()
+                .any(|amount| !amount.is_finite())
+                || round(account.available + account.held) != round(account.total)
+            {
+                return Err(AppError::InvalidSnapshot(format!(
+                    "total {} of client {client} in {currency} is not available {} plus held {}",
+                    account.total, account.available, account.held
+                )));
+            }
+            let (held_disputed, open_shortfall) = disputed
+                .remove(&(client, currency.clone()))
+                .unwrap_or_default();
+            let (mut open_authorizations, held_authorized) = authorized
+                .remove(&(client, currency.clone()))
+                .unwrap_or_default();
+            if round(held_disputed + held_authorized) != round(account.held) {
+                return Err(AppError::InvalidSnapshot(format!(
+                    "held {} of client {client} in {currency} is not its open disputes {held_disputed} plus its open authorizations {held_authorized}",
+                    account.held
+                )));
+            }
+            // Shortfalls of charged back disputes stay owed on top of the open ones.
+            if !account.shortfall.is_finite() || round(account.shortfall) < round(open_shortfall) {
+                return Err(AppError::InvalidSnapshot(format!(
+                    "shortfall {} of client {client} in {currency} is below the shortfall {open_shortfall} of its open disputes",
+                    account.shortfall
+                )));
+            }
+
+            let money = |amount: f64| Money::new(amount, currency.clone());
+            open_authorizations.sort();
+            let mut opened = ClientAccount::opened(
+                client,
+                currency.clone(),
+                &money(account.available),
+                &money(held_disputed),
+                &money(held_authorized),
+                account.locked,
+                open_authorizations,
+            )?;
+            opened.shortfall = account.shortfall;
+            accounts.push(opened);
+        }
+        if let Some(((client, currency), _)) = disputed
+            .into_iter()
+            .find(|(_, (held, shortfall))| *held > 0.0 || *shortfall > 0.0)
+        {
+            return Err(AppError::InvalidSnapshot(format!(
+                "open disputes of client {client} in {currency} have no account"
+            )));
+        }
+        if let Some(((client, currency), _)) = authorized.into_iter().next() {
+            return Err(AppError::InvalidSnapshot(format!(
+                "open authorizations of client {client} in {currency} have no account"
+            )));
+        }
+
+        Ok(Snapshot {
+            accounts,
+            transactions,
+        })
+    }
+}
+
+/// Writes the deposits and authorizations of `transactions` that later records
+/// may still act on, each followed by its open disputes, in the format
+/// `Snapshot::from_files` reads.
+pub fn write_transaction_index(
+    file_path: &str,
+    transactions: &HashMap<u32, TransactionStatus>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut index: Vec<IndexedTransaction> = transactions
+        .values()
+        .flat_map(|status| {
+            let Some(recorded) = status.record.amount else {
+                return Vec::new();
+            };
+            let amount = match status.record.r#type {
+                TransactionType::Deposit => {
+                    recorded - status.charged_back_amount() - status.refunded
+                }
+                TransactionType::Authorize if !status.released => recorded - status.captured,
+                _ => return Vec::new(),
+            };
+            if amount <= 0.0 {
+                return Vec::new();
+            }
+            let indexed = IndexedTransaction {
+                r#type: status.record.r#type.clone(),
+                client: status.record.client,
+                tx: status.record.tx,
+                currency: status.record.currency.clone(),
+                amount,
+                disputed: status.disputed_amount(),
+                timestamp: status.record.timestamp,
+                disputes_raised: status.disputes.len() as u32,
+                dispute: None,
+                held: None,
+                shortfall: 0.0,
+            };
+            let open = status
+                .disputes
+                .iter()
+                .zip(1..)
+                .filter(|(dispute, _)| dispute.state == DisputeState::Open)
+                .map(|(dispute, number)| IndexedTransaction {
+                    r#type: TransactionType::Dispute,
+                    amount: dispute.amount,
+                    disputed: 0.0,
+                    timestamp: None,
+                    disputes_raised: 0,
+                    dispute: Some(number),
+                    held: Some(dispute.held),
+                    shortfall: dispute.shortfall,
+                    ..indexed.clone()
+                });
+            std::iter::once(indexed.clone()).chain(open).collect()
+        })
+        .collect();
+    index.sort_by_key(|indexed| (indexed.tx, indexed.dispute));
+
+    let mut writer = csv::Writer::from_path(file_path)?;
+    for indexed in &index {
+        writer.serialize(indexed)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
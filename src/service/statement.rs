@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+
+use tokio::io::AsyncWriteExt;
+
+use crate::models::account::AccountBalances;
+use crate::models::currency::Currency;
+use crate::service::journal::{JournalEntry, JournalOutcome};
+
+/// Journal entries up to and including input line `as_of_line` and Unix time
+/// `as_of`, sorted by input line. Entries without a timestamp are kept.
+pub fn entries_as_of(
+    mut entries: Vec<JournalEntry>,
+    as_of_line: Option<u64>,
+    as_of: Option<u64>,
+) -> Vec<JournalEntry> {
+    entries.retain(|entry| {
+        as_of_line.is_none_or(|as_of_line| entry.line <= as_of_line)
+            && as_of.is_none_or(|as_of| entry.timestamp.is_none_or(|timestamp| timestamp <= as_of))
+    });
+    // Stable, so entries of the same line keep the order they were applied in.
+    entries.sort_by_key(|entry| entry.line);
+    entries
+}
+
+/// Prints every applied entry of `client` in input order, with the balances
+/// of the account right after it.
+pub async fn print_statement(
+    entries: &[JournalEntry],
+    client: u16,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = tokio::io::stdout();
+    writer
+        .write_all(b"line,type,tx,currency,amount,fee,available,held,total,locked\n")
+        .await?;
+
+    for entry in entries
+        .iter()
+        .filter(|entry| entry.client == client && entry.outcome == JournalOutcome::Applied)
+    {
+        let amount = entry
+            .amount
+            .map(|amount| format!("{:.4}", (amount * 10000.0).round() / 10000.0))
+            .unwrap_or_default();
+        let line = format!(
+            "{},{},{},{},{},{:.4},{:.4},{:.4},{:.4},{}\n",
+            entry.line,
+            entry.r#type,
+            entry.tx,
+            entry.currency,
+            amount,
+            (entry.fee * 10000.0).round() / 10000.0,
+            (entry.available * 10000.0).round() / 10000.0,
+            (entry.held * 10000.0).round() / 10000.0,
+            (entry.total * 10000.0).round() / 10000.0,
+            entry.locked
+        );
+        writer.write_all(line.as_bytes()).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Account states as of the last journal entry of each (client, currency),
+/// ordered by client, then currency.
+pub fn accounts_from_journal(entries: &[JournalEntry]) -> Vec<AccountBalances> {
+    let mut accounts: BTreeMap<(u16, Currency), AccountBalances> = BTreeMap::new();
+    for entry in entries {
+        let account = accounts
+            .entry((entry.client, entry.currency.clone()))
+            .or_insert_with(|| AccountBalances {
+                client: entry.client,
+                currency: entry.currency.clone(),
+                ..AccountBalances::default()
+            });
+        account.available = entry.available;
+        account.held = entry.held;
+        account.total = entry.total;
+        account.locked = entry.locked;
+    }
+    // A client locked in one currency is locked in all of them.
+    let locked: Vec<u16> = accounts
+        .values()
+        .filter(|account| account.locked)
+        .map(|account| account.client)
+        .collect();
+    for account in accounts.values_mut() {
+        account.locked |= locked.contains(&account.client);
+    }
+    accounts.into_values().collect()
+}
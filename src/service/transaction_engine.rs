@@ -1,26 +1,122 @@
 use futures_util::StreamExt;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 use tokio::io::AsyncWriteExt;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tracing::Instrument;
 
-use crate::models::account::ClientAccount;
+use crate::error::AppError;
+use crate::models::account::{AccountBalances, ClientAccount};
+use crate::models::currency::Currency;
+use crate::models::exchange::ExchangeSettings;
+use crate::models::fee::FeeSchedule;
+use crate::models::interest::InterestSchedule;
+use crate::models::ledger::LedgerAccount;
+use crate::models::limits::{Limits, RiskLimits, Velocity};
+use crate::models::money::Money;
+use crate::models::settings::{DisputePolicy, EngineSettings, SECONDS_PER_DAY};
+use crate::models::transaction::DisputeState;
+use crate::models::transaction::TransactionOutcome;
 use crate::models::transaction::TransactionRecord;
 use crate::models::transaction::TransactionStatus;
+use crate::models::transaction::TransactionType;
+use crate::service::idempotency::{IdempotencyStore, ProcessedRecord, RecordKey};
+use crate::service::journal::{Journal, JournalEntry, JournalOutcome};
+use crate::service::metrics::Metrics;
+use crate::service::progress::{CountingReader, Progress};
+use crate::service::snapshot::{self, Snapshot};
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 enum TransactionMssage {
-    Record(TransactionRecord),
+    // Line number of the record in the input, the record, and where to send
+    // its outcome when the submitter waits for it.
+    Record(u64, TransactionRecord, Option<oneshot::Sender<RecordReply>>),
     Terminate,
 }
 
+/// Outcome of a submitted record, sent back to its submitter.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordReply {
+    pub line: u64,
+    pub client: u16,
+    pub tx: u32,
+    pub outcome: JournalOutcome,
+    // Why the record was rejected.
+    pub reason: Option<String>,
+    pub fee: f64,
+    // True when the record was a resubmission answered with its first outcome.
+    pub replayed: bool,
+}
+
+impl RecordReply {
+    fn new(
+        line: u64,
+        record: &TransactionRecord,
+        result: &Result<TransactionOutcome, Box<dyn std::error::Error>>,
+    ) -> Self {
+        let (outcome, reason, fee) = match result {
+            Ok(outcome) if outcome.applied => (JournalOutcome::Applied, None, outcome.fee),
+            Ok(_) => (JournalOutcome::Ignored, None, 0.0),
+            Err(err) => (JournalOutcome::Rejected, Some(err.to_string()), 0.0),
+        };
+        RecordReply {
+            line,
+            client: record.client,
+            tx: record.tx,
+            outcome,
+            reason,
+            fee,
+            replayed: false,
+        }
+    }
+
+    fn replayed(line: u64, earlier: &ProcessedRecord) -> Self {
+        RecordReply {
+            line,
+            client: earlier.client,
+            tx: earlier.tx,
+            outcome: earlier.outcome,
+            reason: (!earlier.reason.is_empty()).then(|| earlier.reason.clone()),
+            fee: earlier.fee,
+            replayed: true,
+        }
+    }
+}
+
+/// Optional columns appended to the account output.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OutputOptions {
+    pub held_breakdown: bool,
+    pub fees: bool,
+    pub overdrawn: bool,
+    pub shortfall: bool,
+    pub interest: bool,
+    // A JSON array of accounts instead of CSV rows.
+    pub json: bool,
+}
+
+// Key: client ID, Value: the client's account in each currency it holds
+pub type ClientAccounts = HashMap<u16, BTreeMap<Currency, ClientAccount>>;
+
 #[derive(Debug)]
 pub struct TransactionEngineService {
-    // Key: client ID, Value: ClientAccount
-    pub client_account: Arc<Mutex<HashMap<u16, crate::models::account::ClientAccount>>>,
+    pub client_account: Arc<Mutex<ClientAccounts>>,
     // Key: transaction ID, Value: TransactionStatus
     pub processed_transactions: Arc<Mutex<HashMap<u32, TransactionStatus>>>,
+    pub settings: Arc<EngineSettings>,
+    pub journal: Option<Arc<Mutex<Journal>>>,
+    pub idempotency: Option<Arc<Mutex<IdempotencyStore>>>,
+    pub metrics: Arc<Metrics>,
+    // How often a file run reports its progress on stderr.
+    pub progress_interval: Option<Duration>,
+    // Workers of records submitted one at a time, kept alive between submissions.
+    workers: Arc<tokio::sync::Mutex<HashMap<u16, mpsc::Sender<TransactionMssage>>>>,
 }
 
 impl Default for TransactionEngineService {
@@ -34,15 +130,133 @@ impl TransactionEngineService {
         TransactionEngineService {
             client_account: Arc::new(Mutex::new(HashMap::new())),
             processed_transactions: Arc::new(Mutex::new(HashMap::new())),
+            settings: Arc::new(EngineSettings::default()),
+            journal: None,
+            idempotency: None,
+            metrics: Arc::new(Metrics::default()),
+            progress_interval: None,
+            workers: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn with_default_currency(mut self, currency: Currency) -> Self {
+        Arc::make_mut(&mut self.settings).default_currency = currency;
+        self
+    }
+
+    pub fn with_fee_schedule(mut self, fee_schedule: FeeSchedule) -> Self {
+        let settings = Arc::make_mut(&mut self.settings);
+        settings.house_account = Some(fee_schedule.house_account);
+        settings.fee_schedule = fee_schedule;
+        self
+    }
+
+    pub fn with_risk_limits(mut self, risk_limits: RiskLimits) -> Self {
+        Arc::make_mut(&mut self.settings).risk_limits = risk_limits;
+        self
+    }
+
+    pub fn with_dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        Arc::make_mut(&mut self.settings).dispute_policy = dispute_policy;
+        self
+    }
+
+    pub fn with_lock_rules(
+        mut self,
+        locked_accepts_deposits: bool,
+        ignore_resolves_after_chargeback: bool,
+    ) -> Self {
+        let settings = Arc::make_mut(&mut self.settings);
+        settings.locked_accepts_deposits = locked_accepts_deposits;
+        settings.ignore_resolves_after_chargeback = ignore_resolves_after_chargeback;
+        self
+    }
+
+    /// Accrues interest by `schedule`, posting it on `accrue` records and, when
+    /// `accrue_at` is set, to every account at that time after the last record.
+    pub fn with_interest(mut self, schedule: InterestSchedule, accrue_at: Option<u64>) -> Self {
+        let settings = Arc::make_mut(&mut self.settings);
+        settings.interest = Some(schedule);
+        settings.accrue_at = accrue_at;
+        self
+    }
+
+    pub fn with_exchange_settings(mut self, exchange: ExchangeSettings) -> Self {
+        Arc::make_mut(&mut self.settings).exchange = exchange;
+        self
+    }
+
+    pub fn with_time_rules(
+        mut self,
+        reorder_window: u64,
+        dispute_window_days: Option<u64>,
+        hold_expiry_days: Option<u64>,
+    ) -> Self {
+        let settings = Arc::make_mut(&mut self.settings);
+        settings.reorder_window = reorder_window;
+        settings.dispute_window_days = dispute_window_days;
+        settings.hold_expiry_days = hold_expiry_days;
+        self
+    }
+
+    pub fn with_as_of(mut self, as_of: Option<u64>) -> Self {
+        Arc::make_mut(&mut self.settings).as_of = as_of;
+        self
+    }
+
+    pub fn with_as_of_line(mut self, as_of_line: Option<u64>) -> Self {
+        Arc::make_mut(&mut self.settings).as_of_line = as_of_line;
+        self
+    }
+
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(Arc::new(Mutex::new(journal)));
+        self
+    }
+
+    /// Answers resubmitted records with their first outcome instead of
+    /// applying them again, and rejects records reusing another's tx id.
+    pub fn with_idempotency(mut self, store: IdempotencyStore) -> Self {
+        self.idempotency = Some(Arc::new(Mutex::new(store)));
+        self
+    }
+
+    /// Starts from the accounts and deposits of `snapshot` instead of empty
+    /// accounts.
+    pub fn with_snapshot(self, snapshot: Snapshot) -> Self {
+        {
+            let mut accounts_guard = self.client_account.lock().unwrap();
+            for account in snapshot.accounts {
+                accounts_guard
+                    .entry(account.client)
+                    .or_default()
+                    .insert(account.currency.clone(), account);
+            }
         }
+        self.processed_transactions
+            .lock()
+            .unwrap()
+            .extend(snapshot.transactions);
+        self
+    }
+
+    pub fn with_progress(mut self, interval: Duration) -> Self {
+        self.progress_interval = Some(interval);
+        self
     }
 
+    #[tracing::instrument(skip(self))]
     pub async fn begin_transactions_from_file(
         &mut self,
         file_path: &str,
         batch_mode: bool,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let file = tokio::fs::File::open(file_path).await?;
+        let progress = Arc::new(Progress::new(file.metadata().await?.len()));
+        let reporter = self
+            .progress_interval
+            .map(|interval| progress.spawn_reporter(Arc::clone(&self.metrics), interval));
+        let file = CountingReader::new(file, Arc::clone(&progress));
         let file = tokio_util::compat::TokioAsyncReadCompatExt::compat(file);
         let mut rdr = csv_async::AsyncReaderBuilder::new()
             .trim(csv_async::Trim::All)
@@ -52,29 +266,113 @@ impl TransactionEngineService {
         let mut records = rdr.deserialize::<TransactionRecord>();
 
         let mut handles = Vec::new();
+        let mut line = 0;
+        let mut latest = None;
         while let Some(result) = records.next().await {
             let record = result?;
+            line += 1;
             let client_id = record.client;
+            tracing::trace!(line, client = client_id, tx = record.tx, "read record");
 
-            if !workers.contains_key(&client_id) {
-                let (handle, tx) = self.spawn_worker(client_id, batch_mode).await?;
-                handles.push(handle);
-                workers.insert(client_id, tx);
+            if self
+                .settings
+                .as_of_line
+                .is_some_and(|as_of_line| line > as_of_line)
+            {
+                break;
+            }
+            if let (Some(as_of), Some(timestamp)) = (self.settings.as_of, record.timestamp)
+                && timestamp > as_of
+            {
+                continue;
             }
+            latest = latest.max(record.timestamp);
 
-            if let Some(sender) = workers.get(&client_id) {
-                sender.send(TransactionMssage::Record(record)).await.ok();
+            let sender = match workers.entry(client_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let (handle, tx) = self.spawn_worker(client_id, batch_mode).await?;
+                    handles.push(handle);
+                    progress.add_client();
+                    entry.insert(tx)
+                }
+            };
+
+            // A closed channel means the worker halted the batch.
+            if sender
+                .send(TransactionMssage::Record(line, record, None))
+                .await
+                .is_err()
+            {
+                break;
             }
+            self.metrics
+                .observe_queue_depth(sender.max_capacity() - sender.capacity());
         }
 
+        tracing::info!(records = line, clients = workers.len(), "finished reading");
         for (_client_id, sender) in workers {
             sender.send(TransactionMssage::Terminate).await.ok();
         }
 
+        let mut halted = None;
         for handle in handles {
-            handle.await.ok();
+            if let Ok(Err(err)) = handle.await {
+                halted.get_or_insert(err);
+            }
+        }
+        if let Some(reporter) = reporter {
+            reporter.abort();
+            eprintln!("{}", progress.report(&self.metrics));
+        }
+
+        // Holds of clients without a later record of their own expire here.
+        if halted.is_none()
+            && let Some(now) = self.settings.as_of.or(latest)
+        {
+            let entries = release_expired_holds_at(
+                &mut self.client_account.lock().unwrap(),
+                &mut self.processed_transactions.lock().unwrap(),
+                &self.settings,
+                now,
+                line + 1,
+            );
+            if let Some(journal) = &self.journal {
+                let mut journal_guard = journal.lock().unwrap();
+                for entry in &entries {
+                    journal_guard.record(entry).ok();
+                }
+            }
         }
 
+        if halted.is_none()
+            && let Some(now) = self.settings.accrue_at
+        {
+            let entries = post_interest_at(
+                &mut self.client_account.lock().unwrap(),
+                &self.settings,
+                now,
+                line + 1,
+            );
+            if let Some(journal) = &self.journal {
+                let mut journal_guard = journal.lock().unwrap();
+                for entry in &entries {
+                    journal_guard.record(entry).ok();
+                }
+            }
+        }
+
+        if let Some(journal) = &self.journal {
+            journal.lock().unwrap().flush()?;
+        }
+
+        if let Some(err) = halted {
+            // Batch mode is all-or-nothing: no balances survive a halted batch.
+            self.client_account.lock().unwrap().clear();
+            return Err(err)?;
+        }
+
+        self.trial_balance()?;
         Ok(())
     }
 
@@ -83,40 +381,96 @@ impl TransactionEngineService {
         client_id: u16,
         batch_mode: bool,
     ) -> Result<
-        (tokio::task::JoinHandle<()>, mpsc::Sender<TransactionMssage>),
+        (
+            tokio::task::JoinHandle<Result<(), AppError>>,
+            mpsc::Sender<TransactionMssage>,
+        ),
         Box<dyn std::error::Error>,
     > {
         let (tx, mut rx) = mpsc::channel::<TransactionMssage>(100);
         let accounts = Arc::clone(&self.client_account);
         let processed = Arc::clone(&self.processed_transactions);
+        let settings = Arc::clone(&self.settings);
+        let journal = self.journal.clone();
+        let idempotency = self.idempotency.clone();
+        let metrics = Arc::clone(&self.metrics);
+
+        let handle = tokio::spawn(
+            async move {
+                // Newest timestamp seen for this client.
+                let mut last_timestamp = None;
+                while let Some(msg) = rx.recv().await {
+                    match msg {
+                        TransactionMssage::Record(line, record, reply) => {
+                            let span = tracing::debug_span!(
+                                "record",
+                                line,
+                                client = client_id,
+                                tx = record.tx,
+                                "type" = %record.r#type,
+                                outcome = tracing::field::Empty,
+                            );
+                            let _entered = span.enter();
+                            let (result, entries, record_reply) = {
+                                let mut accounts_guard = accounts.lock().unwrap();
+                                let mut processed_guard = processed.lock().unwrap();
+                                let mut store_guard =
+                                    idempotency.as_ref().map(|store| store.lock().unwrap());
+                                process_idempotent(
+                                    &mut accounts_guard,
+                                    &mut processed_guard,
+                                    store_guard.as_deref_mut(),
+                                    &settings,
+                                    &mut last_timestamp,
+                                    line,
+                                    &record,
+                                )
+                            };
+
+                            if let Some(journal) = &journal {
+                                let mut journal_guard = journal.lock().unwrap();
+                                for entry in &entries {
+                                    journal_guard.record(entry).ok();
+                                }
+                            }
+                            metrics.record(&record, &result);
+                            span.record("outcome", tracing::field::display(record_reply.outcome));
+                            tracing::debug!(
+                                reason = record_reply.reason.as_deref(),
+                                fee = record_reply.fee,
+                                replayed = record_reply.replayed,
+                                "processed record"
+                            );
+
+                            // Limit breaches are reported even when the run carries on.
+                            if let Err(err) = &result
+                                && let Some(AppError::LimitExceeded(limit)) =
+                                    err.downcast_ref::<AppError>()
+                            {
+                                tracing::warn!(%limit, "limit breach");
+                            }
 
-        let handle = tokio::spawn(async move {
-            while let Some(msg) = rx.recv().await {
-                match msg {
-                    TransactionMssage::Record(record) => {
-                        let mut accounts_guard = accounts.lock().unwrap();
-                        let mut processed_guard = processed.lock().unwrap();
-
-                        let account = accounts_guard
-                            .entry(client_id)
-                            .or_insert_with(|| ClientAccount::new(client_id));
-                        if batch_mode {
-                            let result =
-                                account.apply_transaction_record(&record, &mut processed_guard);
-                            if let Err(err) = result {
-                                panic!(
-                                    "Error processing transaction for client {}: {}",
-                                    client_id, err
-                                );
+                            if let Some(reply) = reply {
+                                reply.send(record_reply).ok();
+                            }
+
+                            if batch_mode && let Err(err) = result {
+                                tracing::warn!(error = %err, "batch halted");
+                                return Err(AppError::BatchHalted {
+                                    client: client_id,
+                                    tx: record.tx,
+                                    reason: err.to_string(),
+                                });
                             }
-                        } else {
-                            let _ = account.apply_transaction_record(&record, &mut processed_guard);
                         }
+                        TransactionMssage::Terminate => break,
                     }
-                    TransactionMssage::Terminate => break,
                 }
+                tracing::debug!("worker finished");
+                Ok(())
             }
-        });
+            .instrument(tracing::info_span!("worker", client = client_id)),
+        );
 
         Ok((handle, tx))
     }
@@ -128,12 +482,10 @@ impl TransactionEngineService {
         // No, but for testing purposes.
         let sorted_client_accounts = {
             let accounts_guard = self.client_account.lock().unwrap();
-            let mut accounts: Vec<ClientAccount> = accounts_guard.values().cloned().collect();
-            accounts.sort_by_key(|account| account.client);
-            accounts
+            sorted_accounts(&accounts_guard)
         };
         for account in &sorted_client_accounts {
-            writer.serialize(account)?;
+            writer.serialize(account.balances())?;
         }
         writer.flush()?;
         Ok(())
@@ -141,31 +493,811 @@ impl TransactionEngineService {
 
     pub async fn print_client_accounts_four_decimal_places(
         &self,
+        options: OutputOptions,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut writer = tokio::io::stdout();
+        print_accounts(&self.balances(), options, &self.settings.default_currency).await
+    }
+
+    /// Balances of every ledger account summed over all clients, by currency.
+    /// Fails when the settlement balance differs from the funds applied
+    /// records moved in and out, the chargeback losses differ from the held
+    /// funds of charged back disputes, or transfers to and from the house
+    /// account do not cancel out.
+    pub fn trial_balance(&self) -> Result<BTreeMap<(Currency, LedgerAccount), f64>, AppError> {
+        const TOLERANCE: f64 = 1e-6;
+        let accounts_guard = self.client_account.lock().unwrap();
+
+        let mut totals: BTreeMap<(Currency, LedgerAccount), f64> = BTreeMap::new();
+        for account in accounts_guard
+            .values()
+            .flat_map(|accounts| accounts.values())
+        {
+            for (ledger_account, balance) in account.ledger().balances() {
+                *totals
+                    .entry((account.currency.clone(), ledger_account))
+                    .or_default() += balance.amount();
+            }
+        }
+
+        // What the ledgers should show, from the records and disputes themselves.
+        let mut expected: BTreeMap<(Currency, LedgerAccount), f64> = BTreeMap::new();
+        for account in accounts_guard
+            .values()
+            .flat_map(|accounts| accounts.values())
+        {
+            *expected
+                .entry((account.currency.clone(), LedgerAccount::Settlement))
+                .or_default() += account.settled();
+        }
+        for status in self.processed_transactions.lock().unwrap().values() {
+            let currency = status
+                .record
+                .currency
+                .clone()
+                .unwrap_or_else(|| self.settings.default_currency.clone());
+            for dispute in &status.disputes {
+                if dispute.state == DisputeState::ChargedBack {
+                    *expected
+                        .entry((currency.clone(), LedgerAccount::ChargebackLoss))
+                        .or_default() += dispute.held;
+                }
+            }
+        }
+        let checked = expected
+            .keys()
+            .chain(totals.keys().filter(|(_, ledger_account)| {
+                matches!(
+                    ledger_account,
+                    LedgerAccount::Settlement | LedgerAccount::ChargebackLoss
+                )
+            }));
+        for (currency, ledger_account) in checked.collect::<BTreeSet<_>>() {
+            let key = (currency.clone(), *ledger_account);
+            let balance = totals.get(&key).copied().unwrap_or_default();
+            let accounted = expected.get(&key).copied().unwrap_or_default();
+            if (balance - accounted).abs() > TOLERANCE {
+                return Err(AppError::LedgerImbalance(format!(
+                    "{ledger_account} in {currency} is {balance} but the records account for {accounted}"
+                )));
+            }
+        }
+        for ((currency, ledger_account), balance) in &totals {
+            if *ledger_account == LedgerAccount::HouseTransfers && balance.abs() > TOLERANCE {
+                return Err(AppError::LedgerImbalance(format!(
+                    "house transfers in {currency} are off by {balance}"
+                )));
+            }
+        }
+        Ok(totals)
+    }
+
+    /// Writes the trial balance as `currency,account,balance` CSV rows.
+    pub fn write_trial_balance(&self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = csv::Writer::from_path(file_path)?;
+        writer.write_record(["currency", "account", "balance"])?;
+        for ((currency, ledger_account), balance) in self.trial_balance()? {
+            writer.write_record([
+                currency.to_string(),
+                ledger_account.to_string(),
+                format!("{:.4}", (balance * 10000.0).round() / 10000.0),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the deposits and authorizations later runs may still act on, to be
+    /// loaded back with their closing balances as a `Snapshot`.
+    pub fn write_transaction_index(
+        &self,
+        file_path: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        snapshot::write_transaction_index(file_path, &self.processed_transactions.lock().unwrap())
+    }
+
+    /// Hands `record` to its client's long-lived worker, spawning it on the
+    /// client's first record. The receiver yields the record's outcome.
+    pub async fn submit(
+        &self,
+        line: u64,
+        record: TransactionRecord,
+    ) -> Result<oneshot::Receiver<RecordReply>, Box<dyn std::error::Error>> {
+        let (reply, receiver) = oneshot::channel();
+        // The guard is dropped before sending, so a full queue only blocks its
+        // own client.
+        let sender = match self.workers.lock().await.entry(record.client) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => {
+                let (_handle, tx) = self.spawn_worker(record.client, false).await?;
+                entry.insert(tx).clone()
+            }
+        };
+        sender
+            .send(TransactionMssage::Record(line, record, Some(reply)))
+            .await
+            .map_err(|err| err.to_string())?;
+        self.metrics
+            .observe_queue_depth(sender.max_capacity() - sender.capacity());
+        Ok(receiver)
+    }
+
+    /// Records waiting for the workers of submitted records.
+    pub async fn queue_depth(&self) -> u64 {
+        self.workers
+            .lock()
+            .await
+            .values()
+            .map(|sender| (sender.max_capacity() - sender.capacity()) as u64)
+            .sum()
+    }
+
+    /// Status of a processed deposit or authorization.
+    pub fn transaction_status(&self, tx: u32) -> Option<TransactionStatus> {
+        self.processed_transactions
+            .lock()
+            .unwrap()
+            .get(&tx)
+            .cloned()
+    }
+
+    /// Every account, ordered by client, then currency.
+    pub fn accounts(&self) -> Vec<ClientAccount> {
+        sorted_accounts(&self.client_account.lock().unwrap())
+    }
 
+    /// Balances of every account, ordered by client, then currency.
+    pub fn balances(&self) -> Vec<AccountBalances> {
+        self.accounts()
+            .iter()
+            .map(ClientAccount::balances)
+            .collect()
+    }
+
+    /// Entries of an in-memory journal.
+    pub fn journal_entries(&self) -> Vec<JournalEntry> {
+        self.journal
+            .as_ref()
+            .map(|journal| journal.lock().unwrap().entries().to_vec())
+            .unwrap_or_default()
+    }
+}
+
+/// Prints accounts with four decimal places.
+pub async fn print_accounts(
+    accounts: &[AccountBalances],
+    options: OutputOptions,
+    default_currency: &Currency,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = tokio::io::stdout();
+
+    if options.json {
+        let rows: Vec<serde_json::Value> = accounts
+            .iter()
+            .map(|account| account_json(account, options))
+            .collect();
         writer
-            .write_all(b"client,available,held,total,locked\n")
+            .write_all(format!("{}\n", serde_json::to_string(&rows)?).as_bytes())
             .await?;
+        writer.flush().await?;
+        return Ok(());
+    }
 
-        let sorted_client_accounts = {
-            let accounts_guard = self.client_account.lock().unwrap();
-            let mut accounts: Vec<ClientAccount> = accounts_guard.values().cloned().collect();
-            accounts.sort_by_key(|account| account.client);
-            accounts
-        };
-        for account in &sorted_client_accounts {
-            let line = format!(
-                "{},{:.4},{:.4},{:.4},{}\n",
-                account.client,
-                (account.available * 10000.0).round() / 10000.0,
-                (account.held * 10000.0).round() / 10000.0,
-                (account.total * 10000.0).round() / 10000.0,
-                account.locked
+    // Currencies are only shown once an account holds a non-default one.
+    let show_currency = accounts
+        .iter()
+        .any(|account| account.currency != *default_currency);
+
+    let mut header = String::from("client,available,held,total,locked");
+    if show_currency {
+        header = String::from("client,currency,available,held,total,locked");
+    }
+    if options.held_breakdown {
+        header.push_str(",held_disputed,held_authorized");
+    }
+    if options.fees {
+        header.push_str(",fees");
+    }
+    if options.overdrawn {
+        header.push_str(",overdrawn");
+    }
+    if options.shortfall {
+        header.push_str(",shortfall");
+    }
+    if options.interest {
+        header.push_str(",interest");
+    }
+    header.push('\n');
+    writer.write_all(header.as_bytes()).await?;
+
+    for account in accounts {
+        let mut client = account.client.to_string();
+        if show_currency {
+            client = format!("{},{}", account.client, account.currency);
+        }
+        let mut line = format!(
+            "{},{:.4},{:.4},{:.4},{}",
+            client,
+            (account.available * 10000.0).round() / 10000.0,
+            (account.held * 10000.0).round() / 10000.0,
+            (account.total * 10000.0).round() / 10000.0,
+            account.locked
+        );
+        if options.held_breakdown {
+            line.push_str(&format!(
+                ",{:.4},{:.4}",
+                (account.held_disputed * 10000.0).round() / 10000.0,
+                (account.held_authorized * 10000.0).round() / 10000.0,
+            ));
+        }
+        if options.fees {
+            line.push_str(&format!(
+                ",{:.4}",
+                (account.fees * 10000.0).round() / 10000.0
+            ));
+        }
+        if options.overdrawn {
+            line.push_str(&format!(",{}", account.overdrawn));
+        }
+        if options.shortfall {
+            line.push_str(&format!(
+                ",{:.4}",
+                (account.shortfall * 10000.0).round() / 10000.0
+            ));
+        }
+        if options.interest {
+            line.push_str(&format!(
+                ",{:.4}",
+                (account.interest * 10000.0).round() / 10000.0
+            ));
+        }
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Processes a record unless `store` has seen it: a resubmission is journaled
+/// as ignored and answered with its first outcome, and a record reusing the tx
+/// id of a different one is rejected. Returns the outcome, the journal entries
+/// and the reply to the submitter.
+fn process_idempotent(
+    accounts: &mut ClientAccounts,
+    processed_transactions: &mut HashMap<u32, TransactionStatus>,
+    store: Option<&mut IdempotencyStore>,
+    settings: &EngineSettings,
+    last_timestamp: &mut Option<u64>,
+    line: u64,
+    record: &TransactionRecord,
+) -> (
+    Result<TransactionOutcome, Box<dyn std::error::Error>>,
+    Vec<JournalEntry>,
+    RecordReply,
+) {
+    let Some(store) = store else {
+        let (result, entries) = process_record(
+            accounts,
+            processed_transactions,
+            settings,
+            last_timestamp,
+            line,
+            record,
+        );
+        let reply = RecordReply::new(line, record, &result);
+        return (result, entries, reply);
+    };
+
+    let currency = record
+        .settlement_currency(processed_transactions, &settings.default_currency)
+        .unwrap_or_else(|_| settings.default_currency.clone());
+    let empty = new_account(record.client, &currency, false);
+    let account = accounts
+        .get(&record.client)
+        .and_then(|client_accounts| client_accounts.get(&currency))
+        .unwrap_or(&empty);
+
+    let key = RecordKey::of(record, processed_transactions);
+    match store.check(&key, record) {
+        Ok(Some(earlier)) => {
+            let entry = JournalEntry::for_record(
+                line,
+                record,
+                0.0,
+                JournalOutcome::Ignored,
+                "replayed".to_string(),
+                account,
             );
-            writer.write_all(line.as_bytes()).await?;
+            let reply = RecordReply::replayed(line, earlier);
+            (Ok(TransactionOutcome::default()), vec![entry], reply)
         }
-        writer.flush().await?;
-        Ok(())
+        Err(conflict) => {
+            let entry = JournalEntry::for_record(
+                line,
+                record,
+                0.0,
+                JournalOutcome::Rejected,
+                conflict.to_string(),
+                account,
+            );
+            let result = Err(conflict.into());
+            let reply = RecordReply::new(line, record, &result);
+            (result, vec![entry], reply)
+        }
+        Ok(None) => {
+            let (result, entries) = process_record(
+                accounts,
+                processed_transactions,
+                settings,
+                last_timestamp,
+                line,
+                record,
+            );
+            let reply = RecordReply::new(line, record, &result);
+            if let Err(err) = store.remember(key, record, &reply) {
+                tracing::error!(error = %err, "failed to store the record's outcome");
+            }
+            (result, entries, reply)
+        }
+    }
+}
+
+// An account with the columns the CSV output would have, always including its
+// currency.
+fn account_json(account: &AccountBalances, options: OutputOptions) -> serde_json::Value {
+    let round = |amount: f64| (amount * 10000.0).round() / 10000.0;
+    let mut row = serde_json::json!({
+        "client": account.client,
+        "currency": account.currency.to_string(),
+        "available": round(account.available),
+        "held": round(account.held),
+        "total": round(account.total),
+        "locked": account.locked,
+    });
+    if options.held_breakdown {
+        row["held_disputed"] = round(account.held_disputed).into();
+        row["held_authorized"] = round(account.held_authorized).into();
+    }
+    if options.fees {
+        row["fees"] = round(account.fees).into();
+    }
+    if options.overdrawn {
+        row["overdrawn"] = account.overdrawn.into();
+    }
+    if options.shortfall {
+        row["shortfall"] = round(account.shortfall).into();
+    }
+    if options.interest {
+        row["interest"] = round(account.interest).into();
+    }
+    row
+}
+
+/// Applies one record to its client's account in the record's settlement
+/// currency and credits any fee to the house account in the same currency.
+/// An exchange also credits the converted amount to the client's account in
+/// the target currency, all under the caller's lock. Funds the record moved
+/// in or out of the engine are tallied on the account, apart from its ledger.
+/// Returns the outcome and the journal entries describing it.
+fn process_record(
+    accounts: &mut ClientAccounts,
+    processed_transactions: &mut HashMap<u32, TransactionStatus>,
+    settings: &EngineSettings,
+    last_timestamp: &mut Option<u64>,
+    line: u64,
+    record: &TransactionRecord,
+) -> (
+    Result<TransactionOutcome, Box<dyn std::error::Error>>,
+    Vec<JournalEntry>,
+) {
+    let client_id = record.client;
+    let house_id = settings.house_account;
+    let currency = record
+        .settlement_currency(processed_transactions, &settings.default_currency)
+        .unwrap_or_else(|_| settings.default_currency.clone());
+
+    // Risk limits count the activity of all of the client's accounts.
+    let limits = settings.risk_limits.limits_for(client_id);
+    // A record that `validate` would report is rejected before anything else.
+    let checked = record
+        .validate()
+        .and_then(|_| check_timestamp(record, *last_timestamp, settings.reorder_window))
+        .and_then(|_| limit_value(record, &currency, &limits, settings))
+        .and_then(|value| {
+            let velocities: Vec<&Velocity> = accounts
+                .get(&client_id)
+                .into_iter()
+                .flat_map(|client_accounts| client_accounts.values())
+                .map(|account| &account.velocity)
+                .collect();
+            Velocity::check(&velocities, record, value, &limits)?;
+            Ok(value)
+        });
+
+    // Price an exchange before any funds move, so a missing rate rejects it whole.
+    let conversion = checked.clone().and_then(|_| match record.r#type {
+        TransactionType::Exchange => {
+            let to = exchange_target(record, &currency)?;
+            let amount = Money::new(record.amount.unwrap_or_default(), currency.clone());
+            Ok(Some(settings.exchange.convert(
+                &amount,
+                to,
+                record.timestamp,
+            )?))
+        }
+        _ => Ok(None),
+    });
+
+    let client_accounts = accounts.entry(client_id).or_default();
+    let mut entries = Vec::new();
+    if let (Some(expiry_days), Some(now), Ok(_)) =
+        (settings.hold_expiry_days, record.timestamp, &conversion)
+    {
+        for account in client_accounts.values_mut() {
+            let expired = account.release_expired_holds(
+                now,
+                expiry_days.saturating_mul(SECONDS_PER_DAY),
+                settings.interest.as_ref(),
+                processed_transactions,
+            );
+            for (tx, amount) in expired {
+                entries.push(JournalEntry::for_credit(
+                    line,
+                    "expired_release",
+                    record,
+                    tx,
+                    amount,
+                    account,
+                ));
+            }
+        }
+    }
+
+    // A chargeback in any currency locks the client as a whole.
+    let client_locked = client_accounts.values().any(|account| account.locked);
+    let account = client_accounts
+        .entry(currency.clone())
+        .or_insert_with(|| new_account(client_id, &currency, client_locked));
+    let result = match &conversion {
+        Ok(_) => account.apply_transaction_record(record, processed_transactions, settings),
+        Err(err) => Err(err.clone().into()),
+    };
+
+    entries.push(match &result {
+        Ok(outcome) if outcome.applied => JournalEntry::for_record(
+            line,
+            record,
+            outcome.fee,
+            JournalOutcome::Applied,
+            String::new(),
+            account,
+        ),
+        Ok(_) => JournalEntry::for_record(
+            line,
+            record,
+            0.0,
+            JournalOutcome::Ignored,
+            String::new(),
+            account,
+        ),
+        Err(err) => JournalEntry::for_record(
+            line,
+            record,
+            0.0,
+            JournalOutcome::Rejected,
+            err.to_string(),
+            account,
+        ),
+    });
+
+    // Only a record that is not rejected advances the client's clock.
+    if result.is_ok()
+        && let Some(timestamp) = record.timestamp
+    {
+        *last_timestamp = Some(last_timestamp.map_or(timestamp, |last| last.max(timestamp)));
     }
+
+    if let (Ok(outcome), Ok(value)) = (&result, &checked)
+        && outcome.applied
+    {
+        account.velocity.record(record, *value, &limits);
+        account.record_settlement(&record.r#type, outcome.amount);
+    }
+
+    if account.locked {
+        for account in client_accounts.values_mut() {
+            account.locked = true;
+        }
+    }
+
+    if result.is_ok()
+        && let Ok(Some(conversion)) = &conversion
+    {
+        let to = conversion.credited.currency();
+        let target = client_accounts
+            .entry(to.clone())
+            .or_insert_with(|| new_account(client_id, to, false));
+        target.accrue_interest_before_change(record.timestamp, settings.interest.as_ref());
+        target
+            .exchange_in(&conversion.credited)
+            .expect("account of the converted currency");
+        entries.push(JournalEntry::for_credit(
+            line,
+            "exchange_credit",
+            record,
+            record.tx,
+            conversion.credited.amount(),
+            target,
+        ));
+        if conversion.spread.amount() > 0.0
+            && let Some(house_id) = house_id
+        {
+            let house = house_account(accounts, house_id, to);
+            house
+                .exchange_in(&conversion.spread)
+                .expect("account of the converted currency");
+            entries.push(JournalEntry::for_credit(
+                line,
+                "spread",
+                record,
+                record.tx,
+                conversion.spread.amount(),
+                house,
+            ));
+        }
+    }
+
+    if let Ok(outcome) = &result
+        && record.r#type == TransactionType::Accrue
+        && outcome.amount > 0.0
+        && let Some(house_id) = house_id
+    {
+        if let Some(account) = accounts
+            .get(&client_id)
+            .and_then(|client_accounts| client_accounts.get(&currency))
+        {
+            entries.push(JournalEntry::for_credit(
+                line,
+                "interest",
+                record,
+                record.tx,
+                outcome.amount,
+                account,
+            ));
+        }
+        entries.push(pay_interest(
+            accounts,
+            house_id,
+            line,
+            record,
+            Money::new(outcome.amount, currency.clone()),
+        ));
+    }
+
+    if let Ok(outcome) = &result
+        && outcome.fee > 0.0
+        && let Some(house_id) = house_id
+    {
+        let house = house_account(accounts, house_id, &currency);
+        house
+            .house_transfer(&Money::new(outcome.fee, currency.clone()))
+            .expect("house account of the fee's currency");
+        entries.push(JournalEntry::for_fee(line, record, outcome.fee, house));
+    }
+
+    (result, entries)
+}
+
+// Debits interest posted to a client from the house account and journals it.
+fn pay_interest(
+    accounts: &mut ClientAccounts,
+    house_id: u16,
+    line: u64,
+    record: &TransactionRecord,
+    amount: Money,
+) -> JournalEntry {
+    let house = house_account(accounts, house_id, amount.currency());
+    let paid = -amount;
+    house
+        .house_transfer(&paid)
+        .expect("house account of the interest's currency");
+    JournalEntry::for_credit(
+        line,
+        "interest_paid",
+        record,
+        record.tx,
+        paid.amount(),
+        house,
+    )
+}
+
+/// Accrues interest up to `now` and posts it to every unlocked account, in
+/// client then currency order, journaling it on `line`.
+fn post_interest_at(
+    accounts: &mut ClientAccounts,
+    settings: &EngineSettings,
+    now: u64,
+    line: u64,
+) -> Vec<JournalEntry> {
+    let (Some(schedule), Some(house_id)) = (&settings.interest, settings.house_account) else {
+        return Vec::new();
+    };
+    let mut clients: Vec<u16> = accounts.keys().copied().collect();
+    clients.sort();
+
+    let mut entries = Vec::new();
+    for client in clients.into_iter().filter(|client| *client != house_id) {
+        let currencies: Vec<Currency> = accounts[&client].keys().cloned().collect();
+        for currency in currencies {
+            let record = TransactionRecord {
+                r#type: TransactionType::Accrue,
+                client,
+                tx: 0,
+                amount: None,
+                dispute: None,
+                currency: Some(currency.clone()),
+                to_currency: None,
+                timestamp: Some(now),
+            };
+            let account = accounts
+                .get_mut(&client)
+                .and_then(|client_accounts| client_accounts.get_mut(&currency))
+                .expect("account listed above");
+            if account.locked {
+                continue;
+            }
+            account.accrue_interest(now, schedule);
+            let posted = account.post_interest(schedule.decimals);
+            if posted > 0.0 {
+                entries.push(JournalEntry::for_credit(
+                    line, "interest", &record, 0, posted, account,
+                ));
+                entries.push(pay_interest(
+                    accounts,
+                    house_id,
+                    line,
+                    &record,
+                    Money::new(posted, currency.clone()),
+                ));
+            }
+        }
+    }
+    entries
+}
+
+// Amount of `record` in the default currency, which risk limits are set in,
+// at the latest rate at the record's time.
+fn limit_value(
+    record: &TransactionRecord,
+    currency: &Currency,
+    limits: &Limits,
+    settings: &EngineSettings,
+) -> Result<f64, AppError> {
+    let amount = record.amount.unwrap_or_default();
+    if *currency == settings.default_currency || !limits.limits_amount(&record.r#type) {
+        return Ok(amount);
+    }
+    settings
+        .exchange
+        .rates
+        .rate(currency, &settings.default_currency, record.timestamp)
+        .map(|rate| amount * rate)
+        .ok_or_else(|| {
+            AppError::LimitExceeded(format!(
+                "no rate to value {currency} in {}",
+                settings.default_currency
+            ))
+        })
+}
+
+// Rejects a record older than the client's newest record by more than the
+// reordering window.
+fn check_timestamp(
+    record: &TransactionRecord,
+    last_timestamp: Option<u64>,
+    reorder_window: u64,
+) -> Result<(), AppError> {
+    match (record.timestamp, last_timestamp) {
+        (Some(timestamp), Some(last)) if timestamp.saturating_add(reorder_window) < last => {
+            Err(AppError::TimestampOutOfOrder {
+                timestamp,
+                latest: last,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Releases the authorization holds of every account that have expired by
+/// `now`, in client then currency order, journaling them on `line`.
+fn release_expired_holds_at(
+    accounts: &mut ClientAccounts,
+    processed_transactions: &mut HashMap<u32, TransactionStatus>,
+    settings: &EngineSettings,
+    now: u64,
+    line: u64,
+) -> Vec<JournalEntry> {
+    let Some(expiry_days) = settings.hold_expiry_days else {
+        return Vec::new();
+    };
+    let mut clients: Vec<u16> = accounts.keys().copied().collect();
+    clients.sort();
+
+    let mut entries = Vec::new();
+    for client in clients {
+        for account in accounts
+            .get_mut(&client)
+            .expect("client listed above")
+            .values_mut()
+        {
+            let expired = account.release_expired_holds(
+                now,
+                expiry_days.saturating_mul(SECONDS_PER_DAY),
+                settings.interest.as_ref(),
+                processed_transactions,
+            );
+            for (tx, amount) in expired {
+                let record = TransactionRecord {
+                    r#type: TransactionType::Release,
+                    client,
+                    tx,
+                    amount: Some(amount),
+                    dispute: None,
+                    currency: Some(account.currency.clone()),
+                    to_currency: None,
+                    timestamp: Some(now),
+                };
+                entries.push(JournalEntry::for_credit(
+                    line,
+                    "expired_release",
+                    &record,
+                    tx,
+                    amount,
+                    account,
+                ));
+            }
+        }
+    }
+    entries
+}
+
+// Target currency of an exchange, which must differ from the source currency.
+fn exchange_target<'a>(
+    record: &'a TransactionRecord,
+    from: &Currency,
+) -> Result<&'a Currency, AppError> {
+    match &record.to_currency {
+        Some(to) if to != from => Ok(to),
+        Some(to) => Err(AppError::InvalidExchange(format!(
+            "cannot exchange {from} into {to}"
+        ))),
+        None => Err(AppError::InvalidExchange("missing to_currency".to_string())),
+    }
+}
+
+fn new_account(client: u16, currency: &Currency, locked: bool) -> ClientAccount {
+    let mut account = ClientAccount::new(client, currency.clone());
+    account.locked = locked;
+    account
+}
+
+fn house_account<'a>(
+    accounts: &'a mut ClientAccounts,
+    house_id: u16,
+    currency: &Currency,
+) -> &'a mut ClientAccount {
+    accounts
+        .entry(house_id)
+        .or_default()
+        .entry(currency.clone())
+        .or_insert_with(|| ClientAccount::new(house_id, currency.clone()))
+}
+
+// Accounts ordered by client, then currency.
+fn sorted_accounts(accounts: &ClientAccounts) -> Vec<ClientAccount> {
+    let mut clients: Vec<&u16> = accounts.keys().collect();
+    clients.sort();
+    clients
+        .into_iter()
+        .flat_map(|client| accounts[client].values().cloned())
+        .collect()
 }
@@ -0,0 +1,68 @@
+use std::collections::HashSet;
+
+use futures_util::StreamExt;
+use tokio::io::AsyncWriteExt;
+
+use crate::error::AppError;
+use crate::models::transaction::{TransactionRecord, TransactionType};
+
+/// A row of the input that could not be processed as written.
+#[derive(Debug, Clone)]
+pub struct InvalidRecord {
+    pub line: u64,
+    pub reason: String,
+}
+
+/// Parses every row of `file_path` and checks it on its own, without applying
+/// anything: unparseable rows, missing or negative amounts, exchanges without a
+/// target, and deposits or authorizations repeating an earlier tx id.
+pub async fn validate_file(
+    file_path: &str,
+) -> Result<Vec<InvalidRecord>, Box<dyn std::error::Error>> {
+    let file = tokio::fs::File::open(file_path).await?;
+    let file = tokio_util::compat::TokioAsyncReadCompatExt::compat(file);
+    let mut rdr = csv_async::AsyncReaderBuilder::new()
+        .trim(csv_async::Trim::All)
+        .create_deserializer(file);
+    let mut records = rdr.deserialize::<TransactionRecord>();
+
+    let mut invalid = Vec::new();
+    let mut opened = HashSet::new();
+    let mut line = 0;
+    while let Some(result) = records.next().await {
+        line += 1;
+        let checked = match result {
+            Ok(record) => record
+                .validate()
+                .map_err(|err| err.to_string())
+                .and_then(|_| {
+                    let opens = matches!(
+                        record.r#type,
+                        TransactionType::Deposit | TransactionType::Authorize
+                    );
+                    if opens && !opened.insert(record.tx) {
+                        return Err(AppError::DuplicateRecord.to_string());
+                    }
+                    Ok(())
+                }),
+            Err(err) => Err(err.to_string()),
+        };
+        if let Err(reason) = checked {
+            invalid.push(InvalidRecord { line, reason });
+        }
+    }
+    Ok(invalid)
+}
+
+/// Prints invalid rows as `line,reason`.
+pub async fn print_invalid(invalid: &[InvalidRecord]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["line", "reason"])?;
+    for record in invalid {
+        writer.write_record([record.line.to_string(), record.reason.clone()])?;
+    }
+    let mut stdout = tokio::io::stdout();
+    stdout.write_all(&writer.into_inner()?).await?;
+    stdout.flush().await?;
+    Ok(())
+}
@@ -1,15 +1,38 @@
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
 /// Test including sample transactions
 /// and verifying output correctness.
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, Stdio};
 use std::str;
 
 fn run_file(file_path: &str, batch_mode: bool) -> String {
-    let mut args = vec!["run", "--", file_path];
     if batch_mode {
-        args.push("--batch");
+        run_file_with_args(file_path, &["--batch"])
+    } else {
+        run_file_with_args(file_path, &[])
     }
+}
+
+fn run_file_with_args(file_path: &str, extra_args: &[&str]) -> String {
+    let mut args = vec![file_path];
+    args.extend_from_slice(extra_args);
+    run_command(&args)
+}
+
+fn run_command(command_args: &[&str]) -> String {
+    run_command_with_status(command_args).0
+}
+
+// Stdout of the command and whether it exited successfully.
+fn run_command_with_status(command_args: &[&str]) -> (String, bool) {
+    let (stdout, code) = run_command_with_exit_code(command_args);
+    (stdout, code == Some(0))
+}
+
+fn run_command_with_exit_code(command_args: &[&str]) -> (String, Option<i32>) {
+    let mut args = vec!["run", "--"];
+    args.extend_from_slice(command_args);
     let output = Command::new("cargo")
         .args(&args)
         .stdout(Stdio::piped())
@@ -17,12 +40,17 @@ fn run_file(file_path: &str, batch_mode: bool) -> String {
         .expect("Failed to start process")
         .wait_with_output()
         .expect("Failed to wait on process");
-    str::from_utf8(&output.stdout)
+    let stdout = str::from_utf8(&output.stdout)
         .expect("Failed to read stdout")
-        .to_string()
+        .to_string();
+    (stdout, output.status.code())
 }
 
 fn create_temp_csv(filename: &str, content: &str) -> String {
+    create_temp_file(filename, content)
+}
+
+fn create_temp_file(filename: &str, content: &str) -> String {
     let path = format!("/tmp/{}", filename);
     let mut file = File::create(&path).expect("Failed to create temp file");
     file.write_all(content.as_bytes())
@@ -354,3 +382,2471 @@ chargeback,1,2,
     assert!(output.contains("200.0000"));
     assert!(output.contains("true"));
 }
+
+// ============ AUTHORIZATION HOLD TESTS ============
+
+#[test]
+fn test_authorize_and_full_capture() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+authorize,1,2,40.0
+capture,1,2,
+";
+    let path = create_temp_csv("test_authorize_full_capture.csv", csv_content);
+    let output = run_file_with_args(&path, &["--held-breakdown"]);
+
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,held_disputed,held_authorized
+1,60.0000,0.0000,60.0000,false,0.0000,0.0000
+"
+    );
+}
+
+#[test]
+fn test_authorize_partial_capture_and_release() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+authorize,1,2,40.0
+capture,1,2,15.0
+void,1,2,
+";
+    let path = create_temp_csv("test_authorize_partial_capture.csv", csv_content);
+    let output = run_file_with_args(&path, &["--held-breakdown"]);
+
+    // 15 captured, remaining 25 released back to available
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,held_disputed,held_authorized
+1,85.0000,0.0000,85.0000,false,0.0000,0.0000
+"
+    );
+}
+
+#[test]
+fn test_held_breakdown_separates_disputes_from_authorizations() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+deposit,1,2,50.0
+authorize,1,3,30.0
+dispute,1,2,
+";
+    let path = create_temp_csv("test_held_breakdown.csv", csv_content);
+    let output = run_file_with_args(&path, &["--held-breakdown"]);
+
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,held_disputed,held_authorized
+1,70.0000,80.0000,150.0000,false,50.0000,30.0000
+"
+    );
+}
+
+#[test]
+fn test_capture_exceeding_hold_rejected() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+authorize,1,2,40.0
+capture,1,2,50.0
+dispute,1,2,
+";
+    let path = create_temp_csv("test_capture_exceeding_hold.csv", csv_content);
+    let output = run_file(&path, false);
+
+    // Over-capture is rejected and authorizations cannot be disputed
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,60.0000,40.0000,100.0000,false\n"
+    );
+
+    let output = run_file(&path, true);
+    assert_eq!(output, "client,available,held,total,locked\n");
+}
+
+// ============ PARTIAL DISPUTE TESTS ============
+
+#[test]
+fn test_partial_dispute_and_partial_chargeback() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+dispute,1,1,30.0
+chargeback,1,1,
+";
+    let path = create_temp_csv("test_partial_dispute_chargeback.csv", csv_content);
+    let output = run_file(&path, false);
+
+    // Only the disputed 30 is charged back
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,70.0000,0.0000,70.0000,true\n"
+    );
+}
+
+#[test]
+fn test_concurrent_partial_disputes_resolved_individually() {
+    let csv_content = "type,client,tx,amount,dispute
+deposit,1,1,100.0,
+dispute,1,1,30.0,
+dispute,1,1,20.0,
+resolve,1,1,,2
+";
+    let path = create_temp_csv("test_concurrent_partial_disputes.csv", csv_content);
+    let output = run_file(&path, false);
+
+    // Second dispute (20) resolved, first (30) still held
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,70.0000,30.0000,100.0000,false\n"
+    );
+}
+
+#[test]
+fn test_partial_dispute_exceeding_undisputed_amount_rejected() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+dispute,1,1,80.0
+dispute,1,1,30.0
+";
+    let path = create_temp_csv("test_partial_dispute_exceeding.csv", csv_content);
+    let output = run_file(&path, false);
+
+    // Only 20 left undisputed, so the second dispute is rejected
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,20.0000,80.0000,100.0000,false\n"
+    );
+
+    let output = run_file(&path, true);
+    assert_eq!(output, "client,available,held,total,locked\n");
+}
+
+#[test]
+fn test_dispute_with_zero_amount_disputes_whole_deposit() {
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 5.0
+dispute, 1, 1, 0.0
+";
+    let path = create_temp_csv("test_dispute_zero_amount.csv", csv_content);
+    let output = run_file(&path, false);
+
+    // A zero amount is the placeholder of inputs without partial disputes
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,0.0000,5.0000,5.0000,false\n"
+    );
+}
+
+#[test]
+fn test_dispute_with_non_finite_amount_rejected() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,10.0
+dispute,1,1,NaN
+dispute,1,1,inf
+deposit,1,2,5.0
+";
+    let path = create_temp_csv("test_dispute_non_finite_amount.csv", csv_content);
+    let (output, code) = run_command_with_exit_code(&[&path]);
+
+    // Both disputes are rejected and the client's later records still apply
+    assert_eq!(code, Some(0));
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,15.0000,0.0000,15.0000,false\n"
+    );
+}
+
+#[test]
+fn test_dispute_of_another_clients_deposit_ignored() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+deposit,2,2,100.0
+dispute,2,1,
+chargeback,2,1,
+";
+    let path = create_temp_csv("test_dispute_other_client.csv", csv_content);
+    let output = run_file(&path, false);
+
+    // Client 2 cannot dispute or charge back client 1's deposit
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,100.0000,0.0000,100.0000,false
+2,100.0000,0.0000,100.0000,false
+"
+    );
+}
+
+// ============ REFUND TESTS ============
+
+#[test]
+fn test_partial_and_remaining_refund() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+deposit,1,2,50.0
+refund,1,1,40.0
+refund,1,1,
+refund,1,1,
+";
+    let path = create_temp_csv("test_partial_and_remaining_refund.csv", csv_content);
+    let output = run_file(&path, false);
+
+    // 40 then the remaining 60 refunded, the third refund has nothing left
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,50.0000,0.0000,50.0000,false\n"
+    );
+
+    let output = run_file(&path, true);
+    assert_eq!(output, "client,available,held,total,locked\n");
+}
+
+#[test]
+fn test_refund_of_disputed_deposit_rejected() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+deposit,1,2,100.0
+dispute,1,1,30.0
+refund,1,1,10.0
+resolve,1,1,
+refund,1,1,
+";
+    let path = create_temp_csv("test_refund_disputed_deposit.csv", csv_content);
+    let output = run_file(&path, false);
+
+    // First refund rejected while disputed, second refunds all 100 after resolve
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,100.0000,0.0000,100.0000,false\n"
+    );
+}
+
+#[test]
+fn test_refunded_part_cannot_be_disputed() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+deposit,1,2,100.0
+refund,1,1,70.0
+dispute,1,1,
+";
+    let path = create_temp_csv("test_refunded_part_not_disputed.csv", csv_content);
+    let output = run_file(&path, false);
+
+    // Only the 30 left un-refunded is disputed
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,100.0000,30.0000,130.0000,false\n"
+    );
+}
+
+// ============ FEE SCHEDULE TESTS ============
+
+static FEE_SCHEDULE: &str = "
+house_account = 99
+
+[tiers]
+gold = [2]
+
+[[fees]]
+type = \"withdrawal\"
+flat = 0.5
+percent = 1.0
+min = 1.0
+max = 5.0
+
+[[fees]]
+type = \"withdrawal\"
+tier = \"gold\"
+percent = 0.1
+
+[[fees]]
+type = \"chargeback\"
+flat = 15.0
+";
+
+// A fee schedule without fees, naming the house account spreads and interest go through.
+static HOUSE_ACCOUNT: &str = "house_account = 99\n";
+
+#[test]
+fn test_withdrawal_fees_by_tier_credited_to_house() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,1000.0
+deposit,2,2,1000.0
+withdrawal,1,3,10.0
+withdrawal,1,4,900.0
+withdrawal,2,5,100.0
+";
+    let path = create_temp_csv("test_withdrawal_fees.csv", csv_content);
+    let fees = create_temp_file("test_withdrawal_fees.toml", FEE_SCHEDULE);
+    let output = run_file_with_args(&path, &["--fee-schedule", &fees]);
+
+    // Client 1: min fee 1.0 on 10, max fee 5.0 on 900; gold client 2: 0.1% of 100
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,fees
+1,84.0000,0.0000,84.0000,false,6.0000
+2,899.9000,0.0000,899.9000,false,0.1000
+99,6.1000,0.0000,6.1000,false,0.0000
+"
+    );
+}
+
+#[test]
+fn test_records_of_house_account_rejected() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+deposit,99,2,50.0
+withdrawal,1,3,10.0
+";
+    let path = create_temp_csv("test_house_account_records.csv", csv_content);
+    let fees = create_temp_file("test_house_account_records.toml", FEE_SCHEDULE);
+    let output = run_file_with_args(&path, &["--fee-schedule", &fees]);
+
+    // Only the withdrawal fee reaches the house account
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,fees
+1,89.0000,0.0000,89.0000,false,1.0000
+99,1.0000,0.0000,1.0000,false,0.0000
+"
+    );
+}
+
+#[test]
+fn test_interest_without_house_account_rejected() {
+    let schedule = create_temp_file("test_interest_without_house.toml", "annual_percent = 1.0\n");
+    let (output, code) = run_command_with_exit_code(&["--interest", &schedule, "--print-config"]);
+
+    assert_eq!(output, "");
+    assert_eq!(code, Some(4));
+}
+
+#[test]
+fn test_withdrawal_rejected_when_fee_not_covered() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+withdrawal,1,2,100.0
+";
+    let path = create_temp_csv("test_withdrawal_fee_not_covered.csv", csv_content);
+    let fees = create_temp_file("test_withdrawal_fee_not_covered.toml", FEE_SCHEDULE);
+    let output = run_file_with_args(&path, &["--fee-schedule", &fees]);
+
+    // Withdrawal and its fee are applied together or not at all
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,fees\n1,100.0000,0.0000,100.0000,false,0.0000\n"
+    );
+}
+
+#[test]
+fn test_chargeback_fee_itemised_in_journal() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+dispute,1,1,
+chargeback,1,1,
+";
+    let path = create_temp_csv("test_chargeback_fee_journal.csv", csv_content);
+    let fees = create_temp_file("test_chargeback_fee_journal.toml", FEE_SCHEDULE);
+    let journal = "/tmp/test_chargeback_fee_journal_out.csv";
+    let output = run_file_with_args(&path, &["--fee-schedule", &fees, "--journal", journal]);
+
+    // The chargeback fee is owed even though it overdraws the account
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,fees
+1,-15.0000,0.0000,-15.0000,true,15.0000
+99,15.0000,0.0000,15.0000,false,0.0000
+"
+    );
+    let journal_content = std::fs::read_to_string(journal).expect("Failed to read journal");
+    assert!(journal_content.contains("3,chargeback,1,1,USD,,,15.0,applied,,-15.0,0.0,-15.0,true"));
+    assert!(journal_content.contains("3,fee,99,1,USD,15.0,,0.0,applied,,15.0,0.0,15.0,false"));
+}
+
+// ============ MULTI-CURRENCY TESTS ============
+
+#[test]
+fn test_balances_kept_per_currency() {
+    let csv_content = "type,client,tx,amount,currency
+deposit,1,1,100.0,
+deposit,1,2,50.0,eur
+withdrawal,1,3,80.0,EUR
+withdrawal,1,4,30.0,EUR
+deposit,2,5,10.0,GBP
+";
+    let path = create_temp_csv("test_balances_per_currency.csv", csv_content);
+    let output = run_file(&path, false);
+
+    // USD funds do not cover the 80 EUR withdrawal
+    assert_eq!(
+        output,
+        "client,currency,available,held,total,locked
+1,EUR,20.0000,0.0000,20.0000,false
+1,USD,100.0000,0.0000,100.0000,false
+2,GBP,10.0000,0.0000,10.0000,false
+"
+    );
+}
+
+#[test]
+fn test_dispute_settles_in_original_currency() {
+    let csv_content = "type,client,tx,amount,currency
+deposit,1,1,100.0,EUR
+deposit,1,2,100.0,USD
+dispute,1,1,,
+dispute,1,2,,EUR
+";
+    let path = create_temp_csv("test_dispute_original_currency.csv", csv_content);
+    let output = run_file(&path, false);
+
+    // The second dispute names a currency other than its deposit's and is rejected
+    assert_eq!(
+        output,
+        "client,currency,available,held,total,locked
+1,EUR,0.0000,100.0000,100.0000,false
+1,USD,100.0000,0.0000,100.0000,false
+"
+    );
+}
+
+#[test]
+fn test_chargeback_locks_every_currency_of_client() {
+    let csv_content = "type,client,tx,amount,currency
+deposit,1,1,100.0,EUR
+deposit,1,2,100.0,USD
+dispute,1,1,,
+chargeback,1,1,,
+deposit,1,3,10.0,USD
+deposit,1,4,10.0,GBP
+";
+    let path = create_temp_csv("test_chargeback_locks_currencies.csv", csv_content);
+    let output = run_file(&path, false);
+
+    assert_eq!(
+        output,
+        "client,currency,available,held,total,locked
+1,EUR,0.0000,0.0000,0.0000,true
+1,GBP,0.0000,0.0000,0.0000,true
+1,USD,100.0000,0.0000,100.0000,true
+"
+    );
+}
+
+// ============ EXCHANGE TESTS ============
+
+static RATES: &str = "timestamp,pair,rate
+1700000000,EUR/USD,1.05
+1700086400,EUR/USD,1.10
+1700000000,GBP/USD,1.25
+";
+
+#[test]
+fn test_exchange_uses_latest_rate_and_inverse_pairs() {
+    let csv_content = "type,client,tx,amount,currency,to_currency
+deposit,1,1,100.0,EUR,
+exchange,1,2,40.0,EUR,USD
+exchange,1,3,22.0,USD,EUR
+";
+    let path = create_temp_csv("test_exchange_latest_rate.csv", csv_content);
+    let rates = create_temp_csv("test_exchange_latest_rate_rates.csv", RATES);
+    let output = run_file_with_args(&path, &["--rates", &rates]);
+
+    // 40 EUR -> 44 USD at 1.10, then 22 USD -> 20 EUR at 1 / 1.10
+    assert_eq!(
+        output,
+        "client,currency,available,held,total,locked
+1,EUR,80.0000,0.0000,80.0000,false
+1,USD,22.0000,0.0000,22.0000,false
+"
+    );
+}
+
+#[test]
+fn test_exchange_spread_and_rounding_credited_to_house() {
+    let csv_content = "type,client,tx,amount,currency,to_currency
+deposit,1,1,100.0,GBP,
+exchange,1,2,33.33,GBP,USD
+";
+    let path = create_temp_csv("test_exchange_spread.csv", csv_content);
+    let rates = create_temp_csv("test_exchange_spread_rates.csv", RATES);
+    let house = create_temp_file("test_exchange_spread_house.toml", HOUSE_ACCOUNT);
+    let output = run_file_with_args(
+        &path,
+        &[
+            "--fee-schedule",
+            &house,
+            "--rates",
+            &rates,
+            "--exchange-spread",
+            "1.0",
+            "--exchange-decimals",
+            "2",
+            "--exchange-rounding",
+            "down",
+        ],
+    );
+
+    // 33.33 GBP -> 41.66 USD (41.6625 rounded down), 0.41 spread to house account 99
+    assert_eq!(
+        output,
+        "client,currency,available,held,total,locked,fees
+1,GBP,66.6700,0.0000,66.6700,false,0.0000
+1,USD,41.2500,0.0000,41.2500,false,0.0000
+99,USD,0.4100,0.0000,0.4100,false,0.0000
+"
+    );
+}
+
+#[test]
+fn test_exchange_without_rate_or_funds_rejected() {
+    let csv_content = "type,client,tx,amount,currency,to_currency
+deposit,1,1,100.0,EUR,
+exchange,1,2,10.0,EUR,JPY
+exchange,1,3,200.0,EUR,USD
+";
+    let path = create_temp_csv("test_exchange_rejected.csv", csv_content);
+    let rates = create_temp_csv("test_exchange_rejected_rates.csv", RATES);
+    let output = run_file_with_args(&path, &["--rates", &rates]);
+
+    // Neither leg of a rejected exchange is applied
+    assert_eq!(
+        output,
+        "client,currency,available,held,total,locked
+1,EUR,100.0000,0.0000,100.0000,false
+"
+    );
+}
+
+#[test]
+fn test_exchange_reusing_deposit_tx_settles_in_own_currency() {
+    let csv_content = "type,client,tx,amount,currency,to_currency
+deposit,1,1,100.0,USD,
+deposit,1,2,100.0,EUR,
+exchange,1,2,11.0,USD,EUR
+";
+    let path = create_temp_csv("test_exchange_reused_tx.csv", csv_content);
+    let rates = create_temp_csv("test_exchange_reused_tx_rates.csv", RATES);
+    let output = run_file_with_args(&path, &["--rates", &rates]);
+
+    // The exchange debits USD even though tx 2 was an EUR deposit
+    assert_eq!(
+        output,
+        "client,currency,available,held,total,locked
+1,EUR,110.0000,0.0000,110.0000,false
+1,USD,89.0000,0.0000,89.0000,false
+"
+    );
+}
+
+#[test]
+fn test_invalid_exchange_settings_rejected() {
+    let spread = run_command_with_exit_code(&["--exchange-spread=-1", "--print-config"]);
+    assert_eq!(spread, (String::new(), Some(2)));
+
+    let rates = create_temp_csv(
+        "test_exchange_infinite_rates.csv",
+        "timestamp,pair,rate\n0,EUR/USD,inf\n",
+    );
+    let path = create_temp_csv(
+        "test_exchange_infinite.csv",
+        "type,client,tx,amount\ndeposit,1,1,1.0\n",
+    );
+    let (output, code) = run_command_with_exit_code(&[&path, "--rates", &rates]);
+    assert_eq!(output, "");
+    assert_eq!(code, Some(4));
+}
+
+// ============ TIMESTAMP TESTS ============
+
+#[test]
+fn test_rejected_record_does_not_advance_clock() {
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,100.0,1000
+withdrawal,1,2,500.0,5000
+deposit,1,3,10.0,1010
+";
+    let path = create_temp_csv("test_rejected_record_clock.csv", csv_content);
+    let output = run_file_with_args(&path, &["--reorder-window-secs", "30"]);
+
+    // tx 2 is rejected for insufficient funds, so tx 3 is only compared with tx 1
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,110.0000,0.0000,110.0000,false
+"
+    );
+}
+
+#[test]
+fn test_out_of_order_timestamps_rejected_outside_window() {
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,100.0,1000
+deposit,1,2,10.0,990
+deposit,1,3,1.0,900
+deposit,2,4,5.0,500
+";
+    let path = create_temp_csv("test_out_of_order_timestamps.csv", csv_content);
+    let output = run_file_with_args(&path, &["--reorder-window-secs", "30"]);
+
+    // tx 2 is within the 30s window, tx 3 is not; client 2 has its own clock
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,110.0000,0.0000,110.0000,false
+2,5.0000,0.0000,5.0000,false
+"
+    );
+
+    let output = run_file_with_args(&path, &["--batch"]);
+    assert_eq!(output, "client,available,held,total,locked\n");
+}
+
+#[test]
+fn test_dispute_after_window_rejected() {
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,100.0,0
+deposit,1,2,50.0,8640000
+dispute,1,1,,10454400
+dispute,1,2,,10454400
+";
+    let path = create_temp_csv("test_dispute_window.csv", csv_content);
+    let output = run_file_with_args(&path, &["--dispute-window-days", "120"]);
+
+    // tx 1 is 121 days old when disputed, tx 2 only 21 days
+    assert_eq!(
+        output,
+        "client,available,held,total,locked\n1,100.0000,50.0000,150.0000,false\n"
+    );
+}
+
+#[test]
+fn test_authorization_hold_released_after_expiry() {
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,100.0,0
+authorize,1,2,40.0,0
+capture,1,2,10.0,86400
+deposit,1,3,5.0,604800
+";
+    let path = create_temp_csv("test_hold_expiry.csv", csv_content);
+    let output = run_file_with_args(&path, &["--hold-expiry-days", "7", "--held-breakdown"]);
+
+    // After 7 days the uncaptured 30 returns to available
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,held_disputed,held_authorized
+1,95.0000,0.0000,95.0000,false,0.0000,0.0000
+"
+    );
+}
+
+#[test]
+fn test_authorization_hold_expires_without_later_record_of_client() {
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,100.0,0
+authorize,1,2,40.0,0
+deposit,2,3,5.0,604800
+";
+    let path = create_temp_csv("test_hold_expiry_other_client.csv", csv_content);
+    let output = run_file_with_args(&path, &["--hold-expiry-days", "7"]);
+
+    // Client 1 sends nothing after the hold, which still expires by the end of the run
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,100.0000,0.0000,100.0000,false
+2,5.0000,0.0000,5.0000,false
+"
+    );
+}
+
+#[test]
+fn test_large_day_windows_do_not_overflow() {
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,100.0,0
+deposit,1,3,50.0,0
+authorize,1,2,40.0,0
+dispute,1,3,,86400
+";
+    let path = create_temp_csv("test_large_day_windows.csv", csv_content);
+    let (output, code) = run_command_with_exit_code(&[
+        &path,
+        "--hold-expiry-days",
+        "300000000000000",
+        "--dispute-window-days",
+        "300000000000000",
+    ]);
+
+    assert_eq!(code, Some(0));
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,60.0000,90.0000,150.0000,false
+"
+    );
+}
+
+#[test]
+fn test_as_of_balances() {
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,100.0,100
+deposit,2,2,50.0,150
+withdrawal,1,3,30.0,200
+deposit,2,4,25.0,250
+";
+    let path = create_temp_csv("test_as_of_balances.csv", csv_content);
+    let output = run_file_with_args(&path, &["--as-of", "200"]);
+
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,70.0000,0.0000,70.0000,false
+2,50.0000,0.0000,50.0000,false
+"
+    );
+}
+
+// ============ STATEMENT TESTS ============
+
+static STATEMENT_TRANSACTIONS: &str = "type,client,tx,amount,timestamp
+deposit,1,1,100.0,100
+deposit,2,2,50.0,150
+withdrawal,1,3,30.0,200
+withdrawal,1,4,500.0,250
+dispute,1,1,,300
+deposit,2,5,25.0,350
+";
+
+#[test]
+fn test_statement_lists_applied_transactions_with_running_balances() {
+    let path = create_temp_csv("test_statement_running.csv", STATEMENT_TRANSACTIONS);
+    let output = run_command(&["statement", &path, "--client", "1"]);
+
+    // The rejected withdrawal on line 4 and dispute on line 5 are left out
+    assert_eq!(
+        output,
+        "line,type,tx,currency,amount,fee,available,held,total,locked
+1,deposit,1,USD,100.0000,0.0000,100.0000,0.0000,100.0000,false
+3,withdrawal,3,USD,30.0000,0.0000,70.0000,0.0000,70.0000,false
+"
+    );
+}
+
+#[test]
+fn test_statement_as_of_line_prints_every_account() {
+    let path = create_temp_csv("test_statement_as_of_line.csv", STATEMENT_TRANSACTIONS);
+    let output = run_command(&["statement", &path, "--as-of-line", "3"]);
+
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,70.0000,0.0000,70.0000,false
+2,50.0000,0.0000,50.0000,false
+"
+    );
+}
+
+#[test]
+fn test_statement_from_journal_as_of_timestamp() {
+    let path = create_temp_csv("test_statement_journal.csv", STATEMENT_TRANSACTIONS);
+    let journal = "/tmp/test_statement_journal_out.csv";
+    run_file_with_args(&path, &["--journal", journal]);
+
+    let output = run_command(&["statement", journal, "--from-journal", "--as-of", "200"]);
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,70.0000,0.0000,70.0000,false
+2,50.0000,0.0000,50.0000,false
+"
+    );
+
+    let output = run_command(&["statement", journal, "--from-journal", "--client", "2"]);
+    assert_eq!(
+        output,
+        "line,type,tx,currency,amount,fee,available,held,total,locked
+2,deposit,2,USD,50.0000,0.0000,50.0000,0.0000,50.0000,false
+6,deposit,5,USD,25.0000,0.0000,75.0000,0.0000,75.0000,false
+"
+    );
+}
+
+// ============ LIMIT TESTS ============
+
+static WITHDRAWAL_LIMITS: &str = "
+[tiers]
+vip = [2]
+
+[default]
+max_withdrawal = 100.0
+max_daily_withdrawal = 150.0
+
+[tier.vip]
+max_withdrawal = 1000.0
+max_daily_withdrawal = 1000.0
+
+[client.3]
+max_withdrawal = 10.0
+";
+
+#[test]
+fn test_withdrawal_limits_per_tier_and_client() {
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,1000.0,0
+deposit,2,2,1000.0,0
+deposit,3,3,1000.0,0
+withdrawal,1,4,200.0,10
+withdrawal,1,5,100.0,20
+withdrawal,1,6,60.0,30
+withdrawal,1,7,60.0,86400
+withdrawal,2,8,500.0,10
+withdrawal,3,9,20.0,10
+withdrawal,3,10,10.0,20
+";
+    let path = create_temp_csv("test_withdrawal_limits.csv", csv_content);
+    let limits = create_temp_file("test_withdrawal_limits.toml", WITHDRAWAL_LIMITS);
+    let output = run_file_with_args(&path, &["--limits", &limits]);
+
+    // Client 1: tx 4 above the single limit, tx 6 above the daily limit, tx 7 on the next day
+    // Client 2: vip limits; client 3: its own single limit
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,840.0000,0.0000,840.0000,false
+2,500.0000,0.0000,500.0000,false
+3,990.0000,0.0000,990.0000,false
+"
+    );
+}
+
+#[test]
+fn test_velocity_limits_over_windows() {
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,60.0,0
+deposit,1,2,50.0,10
+deposit,1,3,40.0,20
+withdrawal,1,4,10.0,30
+withdrawal,1,5,10.0,40
+withdrawal,1,6,10.0,70
+deposit,1,7,50.0,3600
+";
+    let limits_content = "
+[default]
+max_transactions = 3
+transaction_window_secs = 60
+max_deposit = 100.0
+deposit_window_secs = 3600
+";
+    let path = create_temp_csv("test_velocity_limits.csv", csv_content);
+    let limits = create_temp_file("test_velocity_limits.toml", limits_content);
+    let journal = "/tmp/test_velocity_limits_out.csv";
+    let output = run_file_with_args(&path, &["--limits", &limits, "--journal", journal]);
+
+    // tx 2 exceeds the hourly deposits, tx 5 is a 4th record within a minute
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,130.0000,0.0000,130.0000,false
+"
+    );
+
+    let journal_content = std::fs::read_to_string(journal).expect("Failed to read journal");
+    assert!(journal_content.contains(
+        "2,deposit,1,2,USD,50.0,10,0.0,rejected,Limit exceeded: deposits above 100 in 3600s,60.0,0.0,60.0,false"
+    ));
+    assert!(journal_content.contains(
+        "5,withdrawal,1,5,USD,10.0,40,0.0,rejected,Limit exceeded: more than 3 transactions in 60s,90.0,0.0,90.0,false"
+    ));
+}
+
+#[test]
+fn test_daily_withdrawal_limit_spans_currencies() {
+    let csv_content = "type,client,tx,amount,currency,timestamp
+deposit,1,1,1000.0,USD,1700000000
+deposit,1,2,1000.0,EUR,1700000000
+withdrawal,1,3,100.0,USD,1700000010
+withdrawal,1,4,50.0,EUR,1700000020
+withdrawal,1,5,5.0,EUR,1700000030
+";
+    let limits_content = "
+[default]
+max_daily_withdrawal = 150.0
+";
+    let path = create_temp_csv("test_daily_limit_currencies.csv", csv_content);
+    let limits = create_temp_file("test_daily_limit_currencies.toml", limits_content);
+    let rates = create_temp_file("test_daily_limit_currencies_rates.csv", RATES);
+    let output = run_file_with_args(&path, &["--limits", &limits, "--rates", &rates]);
+
+    // 50 EUR is worth 52.5 USD, which would take the day's withdrawals past 150 USD
+    assert_eq!(
+        output,
+        "client,currency,available,held,total,locked
+1,EUR,995.0000,0.0000,995.0000,false
+1,USD,900.0000,0.0000,900.0000,false
+"
+    );
+}
+
+#[test]
+fn test_windowed_limits_need_timestamps() {
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,100.0,0
+withdrawal,1,2,10.0,
+deposit,1,3,5.0,
+";
+    let limits_content = "
+[default]
+max_daily_withdrawal = 150.0
+";
+    let path = create_temp_csv("test_windowed_limits_timestamps.csv", csv_content);
+    let limits = create_temp_file("test_windowed_limits_timestamps.toml", limits_content);
+    let journal = "/tmp/test_windowed_limits_timestamps_out.csv";
+    let output = run_file_with_args(&path, &["--limits", &limits, "--journal", journal]);
+
+    // Only withdrawals are under a windowed limit
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,105.0000,0.0000,105.0000,false
+"
+    );
+
+    let journal_content = std::fs::read_to_string(journal).expect("Failed to read journal");
+    assert!(
+        journal_content.contains("rejected,Record needs a timestamp for a windowed risk limit")
+    );
+}
+
+#[test]
+fn test_limits_with_unknown_tier_rejected() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+";
+    let limits_content = "
+[tier.gold]
+max_withdrawal = 10.0
+";
+    let path = create_temp_csv("test_limits_unknown_tier.csv", csv_content);
+    let limits = create_temp_file("test_limits_unknown_tier.toml", limits_content);
+    let output = run_file_with_args(&path, &["--limits", &limits]);
+
+    assert_eq!(output, "");
+}
+
+// ============ CREDIT LINE TESTS ============
+
+#[test]
+fn test_withdrawal_within_credit_line() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,50.0
+deposit,2,2,50.0
+withdrawal,1,3,120.0
+withdrawal,1,4,40.0
+withdrawal,2,5,60.0
+";
+    let limits_content = "
+[client.1]
+credit_limit = 100.0
+";
+    let path = create_temp_csv("test_credit_line_withdrawal.csv", csv_content);
+    let limits = create_temp_file("test_credit_line_withdrawal.toml", limits_content);
+    let output = run_file_with_args(&path, &["--limits", &limits]);
+
+    // Client 1 may go down to -100; client 2 has no credit line
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,-70.0000,0.0000,-70.0000,false
+2,50.0000,0.0000,50.0000,false
+"
+    );
+}
+
+#[test]
+fn test_dispute_of_spent_funds_covered_by_credit_line() {
+    let limits_content = "
+[client.1]
+credit_limit = 1.0
+";
+    let limits = create_temp_file("test_credit_line_dispute.toml", limits_content);
+    let output = run_file_with_args(
+        "./src/test/sample_transactions_with_dispute_with_non_sufficient_fund_left.csv",
+        &["--limits", &limits],
+    );
+
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,-0.5000,2.0000,1.5000,false
+2,1.0000,0.0000,1.0000,false
+"
+    );
+}
+
+#[test]
+fn test_dispute_of_spent_funds_with_negative_policy() {
+    let output = run_file_with_args(
+        "./src/test/sample_transactions_with_dispute_with_non_sufficient_fund_left.csv",
+        &["--dispute-policy", "negative"],
+    );
+
+    // The dispute is held in full, the account flagged and the uncovered part reported
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,overdrawn,shortfall
+1,-0.5000,2.0000,1.5000,false,true,0.5000
+2,1.0000,0.0000,1.0000,false,false,0.0000
+"
+    );
+}
+
+// ============ DISPUTE POLICY TESTS ============
+
+#[test]
+fn test_dispute_of_spent_funds_with_hold_policy() {
+    let output = run_file_with_args(
+        "./src/test/sample_transactions_with_dispute_with_non_sufficient_fund_left.csv",
+        &["--dispute-policy", "hold"],
+    );
+
+    // Only the 1.5 left is held; the other 0.5 is a shortfall
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,shortfall
+1,0.0000,1.5000,1.5000,false,0.5000
+2,1.0000,0.0000,1.0000,false,0.0000
+"
+    );
+}
+
+#[test]
+fn test_shortfall_cleared_by_resolve_and_kept_by_chargeback() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+withdrawal,1,2,70.0
+dispute,1,1,
+chargeback,1,1,
+deposit,2,3,100.0
+withdrawal,2,4,40.0
+dispute,2,3,
+resolve,2,3,
+";
+    let path = create_temp_csv("test_shortfall_resolve_chargeback.csv", csv_content);
+    let output = run_file_with_args(&path, &["--dispute-policy", "hold"]);
+
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,shortfall
+1,0.0000,0.0000,0.0000,true,70.0000
+2,60.0000,0.0000,60.0000,false,0.0000
+"
+    );
+}
+
+// ============ POLICY TESTS ============
+
+static LOCK_POLICY: &str = "
+batch = true
+locked_accepts_deposits = true
+ignore_resolves_after_chargeback = true
+";
+
+#[test]
+fn test_policy_file_controls_locked_account_rules() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+dispute,1,1,
+chargeback,1,1,
+resolve,1,1,
+deposit,1,2,50.0
+";
+    let path = create_temp_csv("test_policy_lock_rules.csv", csv_content);
+    let policy = create_temp_file("test_policy_lock_rules.toml", LOCK_POLICY);
+    let output = run_file_with_args(&path, &["--config", &policy]);
+
+    // In batch mode the resolve would otherwise halt the run
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,50.0000,0.0000,50.0000,true
+"
+    );
+
+    // Without the policy the locked account rejects both records
+    let output = run_file(&path, false);
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,0.0000,0.0000,0.0000,true
+"
+    );
+}
+
+#[test]
+fn test_print_config_with_overrides() {
+    let policy = create_temp_file("test_print_config.toml", LOCK_POLICY);
+    let output = run_command(&[
+        "--config",
+        &policy,
+        "--exchange-decimals",
+        "2",
+        "--print-config",
+    ]);
+
+    assert_eq!(
+        output,
+        "batch = true
+idempotent = false
+default_currency = \"USD\"
+dispute_policy = \"reject\"
+locked_accepts_deposits = true
+ignore_resolves_after_chargeback = true
+reorder_window_secs = 0
+exchange_spread = 0.0
+exchange_decimals = 2
+exchange_rounding = \"nearest\"
+"
+    );
+}
+
+#[test]
+fn test_command_line_disables_policy_flags() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+withdrawal,1,2,500.0
+deposit,1,3,50.0
+";
+    let path = create_temp_csv("test_policy_flags_disabled.csv", csv_content);
+    let policy = create_temp_file("test_policy_flags_disabled.toml", LOCK_POLICY);
+    let output = run_file_with_args(&path, &["--config", &policy, "--no-batch"]);
+
+    // The policy's batch mode would halt the run at tx 2
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,150.0000,0.0000,150.0000,false
+"
+    );
+}
+
+#[test]
+fn test_print_config_reproduces_run_options() {
+    let fees = create_temp_file("test_print_config_run_fees.toml", HOUSE_ACCOUNT);
+    let interest = create_temp_file(
+        "test_print_config_run_interest.toml",
+        "annual_percent = 1.0\n",
+    );
+    let snapshot = create_temp_csv(
+        "test_print_config_run_snapshot.csv",
+        "client,available,held,total,locked\n1,10.0,0.0,10.0,false\n",
+    );
+    let policy = create_temp_file(
+        "test_print_config_run.toml",
+        &format!("fee_schedule = \"{fees}\"\ninterest = \"{interest}\"\n"),
+    );
+    let output = run_command(&[
+        "--config",
+        &policy,
+        "--accrue-at",
+        "86400",
+        "--as-of",
+        "100",
+        "--snapshot",
+        &snapshot,
+        "--print-config",
+    ]);
+
+    // --accrue-at takes the interest schedule of the policy file
+    assert!(output.contains("accrue_at = 86400\nas_of = 100\n"));
+    assert!(output.contains(&format!("snapshot = \"{snapshot}\"\n")));
+}
+
+#[test]
+fn test_policy_with_unknown_setting_rejected() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+";
+    let path = create_temp_csv("test_policy_unknown_setting.csv", csv_content);
+    let policy = create_temp_file("test_policy_unknown_setting.toml", "bacth = true\n");
+    let output = run_file_with_args(&path, &["--config", &policy]);
+
+    assert_eq!(output, "");
+}
+
+// ============ INTEREST TESTS ============
+
+#[test]
+fn test_accrue_records_post_interest_by_tier_with_residuals() {
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,1000.0,0
+deposit,2,2,1000.0,0
+deposit,3,3,150.0,0
+accrue,1,4,,864000
+accrue,2,5,,864000
+accrue,3,6,,86400
+accrue,3,7,,172800
+";
+    let schedule_content = "
+day_count = \"act/365\"
+decimals = 2
+annual_percent = 3.65
+
+[tiers]
+gold = [2]
+
+[tier_rates]
+gold = 7.3
+";
+    let path = create_temp_csv("test_accrue_records.csv", csv_content);
+    let schedule = create_temp_file("test_accrue_records.toml", schedule_content);
+    let house = create_temp_file("test_accrue_records_house.toml", HOUSE_ACCOUNT);
+    let output = run_file_with_args(&path, &["--fee-schedule", &house, "--interest", &schedule]);
+
+    // Client 3 earns 0.015 a day: 0.01 is posted, then 0.02 with the carried residual
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,fees,interest
+1,1001.0000,0.0000,1001.0000,false,0.0000,1.0000
+2,1002.0000,0.0000,1002.0000,false,0.0000,2.0000
+3,150.0300,0.0000,150.0300,false,0.0000,0.0300
+99,-3.0300,0.0000,-3.0300,false,0.0000,0.0000
+"
+    );
+}
+
+#[test]
+fn test_accrue_at_end_of_run_follows_balance_changes() {
+    // 2024-01-01, 2024-01-16
+    let csv_content = "type,client,tx,amount,timestamp
+deposit,1,1,1000.0,1704067200
+withdrawal,1,2,500.0,1705363200
+";
+    let schedule_content = "
+day_count = \"30/360\"
+annual_percent = 3.6
+";
+    let path = create_temp_csv("test_accrue_at.csv", csv_content);
+    let schedule = create_temp_file("test_accrue_at.toml", schedule_content);
+    let house = create_temp_file("test_accrue_at_house.toml", HOUSE_ACCOUNT);
+    let journal = "/tmp/test_accrue_at_out.csv";
+    // 2024-02-01
+    let output = run_file_with_args(
+        &path,
+        &[
+            "--fee-schedule",
+            &house,
+            "--interest",
+            &schedule,
+            "--accrue-at",
+            "1706745600",
+            "--journal",
+            journal,
+        ],
+    );
+
+    // 15 days on 1000 and 15 days on 500 at 3.6%
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,fees,interest
+1,502.2500,0.0000,502.2500,false,0.0000,2.2500
+99,-2.2500,0.0000,-2.2500,false,0.0000,0.0000
+"
+    );
+
+    let journal_content = std::fs::read_to_string(journal).expect("Failed to read journal");
+    assert!(
+        journal_content
+            .contains("3,interest,1,0,USD,2.25,1706745600,0.0,applied,,502.25,0.0,502.25,false")
+    );
+    assert!(
+        journal_content.contains(
+            "3,interest_paid,99,0,USD,-2.25,1706745600,0.0,applied,,-2.25,0.0,-2.25,false"
+        )
+    );
+}
+
+#[test]
+fn test_interest_accrues_before_exchange_credit() {
+    let csv_content = "type,client,tx,amount,currency,to_currency,timestamp
+deposit,1,1,100.0,EUR,,0
+deposit,1,2,1000.0,USD,,0
+exchange,1,3,1000.0,USD,EUR,8640000
+accrue,1,4,,EUR,,17280000
+";
+    let path = create_temp_csv("test_interest_before_exchange.csv", csv_content);
+    let rates = create_temp_csv(
+        "test_interest_before_exchange_rates.csv",
+        "timestamp,pair,rate\n0,USD/EUR,1.0\n",
+    );
+    let schedule = create_temp_file(
+        "test_interest_before_exchange.toml",
+        "annual_percent = 10.0\ndecimals = 2\n",
+    );
+    let house = create_temp_file("test_interest_before_exchange_house.toml", HOUSE_ACCOUNT);
+    let output = run_file_with_args(
+        &path,
+        &[
+            "--fee-schedule",
+            &house,
+            "--rates",
+            &rates,
+            "--interest",
+            &schedule,
+        ],
+    );
+
+    // 100 EUR for 100 days (2.7397) then 1100 EUR for 100 days (30.1370), rounded down
+    assert_eq!(
+        output,
+        "client,currency,available,held,total,locked,fees,interest
+1,EUR,1132.8700,0.0000,1132.8700,false,0.0000,32.8700
+1,USD,0.0000,0.0000,0.0000,false,0.0000,0.0000
+99,EUR,-32.8700,0.0000,-32.8700,false,0.0000,0.0000
+"
+    );
+}
+
+#[test]
+fn test_interest_decimals_above_ten_rejected() {
+    let schedule = create_temp_file(
+        "test_interest_decimals_above_ten.toml",
+        "annual_percent = 1.0\ndecimals = 400\n",
+    );
+    let house = create_temp_file("test_interest_decimals_above_ten_house.toml", HOUSE_ACCOUNT);
+    let path = create_temp_csv(
+        "test_interest_decimals_above_ten.csv",
+        "type,client,tx,amount\ndeposit,1,1,1.0\n",
+    );
+    let (output, code) =
+        run_command_with_exit_code(&[&path, "--fee-schedule", &house, "--interest", &schedule]);
+
+    assert_eq!(output, "");
+    assert_eq!(code, Some(4));
+}
+
+// ============ LEDGER TESTS ============
+
+#[test]
+fn test_trial_balance_of_every_operation() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+deposit,2,2,50.0
+withdrawal,1,3,10.0
+authorize,1,4,20.0
+capture,1,4,5.0
+dispute,2,2,
+chargeback,2,2,
+";
+    let path = create_temp_csv("test_trial_balance.csv", csv_content);
+    let fees = create_temp_file("test_trial_balance.toml", FEE_SCHEDULE);
+    let trial_balance = "/tmp/test_trial_balance_out.csv";
+    run_file_with_args(
+        &path,
+        &["--fee-schedule", &fees, "--trial-balance", trial_balance],
+    );
+
+    // Fees paid by clients 1 and 2 are received by house account 99
+    let content = std::fs::read_to_string(trial_balance).expect("Failed to read trial balance");
+    assert_eq!(
+        content,
+        "currency,account,balance
+USD,available,70.0000
+USD,held_disputed,0.0000
+USD,held_authorized,15.0000
+USD,settlement,-135.0000
+USD,chargeback_loss,50.0000
+USD,house_transfers,0.0000
+"
+    );
+}
+
+#[test]
+fn test_trial_balance_of_exchange() {
+    let csv_content = "type,client,tx,amount,currency,to_currency
+deposit,1,1,100.0,USD,
+exchange,1,2,100.0,USD,EUR
+";
+    let path = create_temp_csv("test_trial_balance_exchange.csv", csv_content);
+    let rates = create_temp_file("test_trial_balance_exchange_rates.csv", RATES);
+    let house = create_temp_file("test_trial_balance_exchange_house.toml", HOUSE_ACCOUNT);
+    let trial_balance = "/tmp/test_trial_balance_exchange_out.csv";
+    run_file_with_args(
+        &path,
+        &[
+            "--fee-schedule",
+            &house,
+            "--rates",
+            &rates,
+            "--exchange-spread",
+            "1",
+            "--trial-balance",
+            trial_balance,
+        ],
+    );
+
+    // The spread is part of the EUR side of the exchange
+    let content = std::fs::read_to_string(trial_balance).expect("Failed to read trial balance");
+    assert_eq!(
+        content,
+        "currency,account,balance
+EUR,available,90.9091
+EUR,exchange,-90.9091
+USD,available,0.0000
+USD,settlement,-100.0000
+USD,exchange,100.0000
+"
+    );
+}
+
+// ============ RECONCILE TESTS ============
+
+static RECONCILE_TRANSACTIONS: &str = "type,client,tx,amount
+deposit,1,1,100.0
+deposit,2,2,50.0
+deposit,4,3,1.0
+";
+
+#[test]
+fn test_reconcile_matching_balances() {
+    let path = create_temp_csv("test_reconcile_match.csv", RECONCILE_TRANSACTIONS);
+    let expected = create_temp_file(
+        "test_reconcile_match_expected.csv",
+        "client,available,held,total,locked
+1,100.0000,0.0000,100.0000,false
+2,50.0000,0.0000,50.0000,false
+4,1.0000,0.0000,1.0000,false
+",
+    );
+    let (output, success) = run_command_with_status(&["reconcile", &path, &expected]);
+
+    assert_eq!(output, "client,currency,issue,expected,actual,difference\n");
+    assert!(success);
+}
+
+#[test]
+fn test_reconcile_reports_breaks() {
+    let path = create_temp_csv("test_reconcile_breaks.csv", RECONCILE_TRANSACTIONS);
+    let expected = create_temp_file(
+        "test_reconcile_breaks_expected.csv",
+        "client,available,held,total,locked
+1,90.0,0.0,90.0,false
+2,50.0,0.0,50.0,true
+3,5.0,0.0,5.0,false
+",
+    );
+    let (output, success) = run_command_with_status(&["reconcile", &path, &expected]);
+
+    assert_eq!(
+        output,
+        "client,currency,issue,expected,actual,difference
+1,USD,available,90.0000,100.0000,10.0000
+1,USD,total,90.0000,100.0000,10.0000
+2,USD,locked,true,false,
+3,USD,missing,,,
+4,USD,extra,,,
+"
+    );
+    assert!(!success);
+}
+
+// ============ DIFF TESTS ============
+
+static DIFF_TRANSACTIONS: &str = "type,client,tx,amount
+deposit,1,1,100.0
+deposit,2,2,50.0
+withdrawal,2,3,30.0
+dispute,2,2,
+deposit,3,4,10.0
+chargeback,2,2,
+";
+
+#[test]
+fn test_diff_account_outputs() {
+    let path = create_temp_csv("test_diff_outputs.csv", DIFF_TRANSACTIONS);
+    let before = create_temp_file("test_diff_outputs_before.csv", &run_file(&path, false));
+    let after = create_temp_file(
+        "test_diff_outputs_after.csv",
+        &run_file_with_args(&path, &["--dispute-policy", "hold"]),
+    );
+    let output = run_command(&["diff", &before, &after]);
+
+    // Only client 2's dispute and chargeback are applied under the hold policy
+    assert_eq!(
+        output,
+        "client,currency,available,held,total,locked
+2,USD,-20.0000,0.0000,-20.0000,false->true
+"
+    );
+}
+
+#[test]
+fn test_diff_journals_reports_first_divergence() {
+    let path = create_temp_csv("test_diff_journals.csv", DIFF_TRANSACTIONS);
+    let before = "/tmp/test_diff_journals_before.csv";
+    let after = "/tmp/test_diff_journals_after.csv";
+    run_file_with_args(&path, &["--journal", before]);
+    run_file_with_args(&path, &["--journal", after, "--dispute-policy", "hold"]);
+    let output = run_command(&["diff", before, after, "--journals"]);
+
+    assert_eq!(
+        output,
+        "client,currency,available,held,total,locked,first_divergence
+2,USD,-20.0000,0.0000,-20.0000,false->true,line 4 dispute tx 2
+"
+    );
+}
+
+// ============ SERVER TESTS ============
+
+// Running `serve` or `listen` process, stopped when dropped so a failing test does not
+// leave it behind.
+struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+// Starts `serve` or `listen` on a free localhost port.
+fn start_server(command: &str) -> Server {
+    let mut child = Command::new("cargo")
+        .args(["run", "--", command, "--addr", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start process");
+    let mut line = String::new();
+    BufReader::new(child.stdout.as_mut().expect("Failed to open stdout"))
+        .read_line(&mut line)
+        .expect("Failed to read stdout");
+    let addr = line
+        .trim()
+        .strip_prefix("Listening on ")
+        .expect("Server did not report its address")
+        .to_string();
+    Server { child, addr }
+}
+
+// Status code and body of a single HTTP/1.1 request.
+fn http_request(addr: &str, method: &str, path: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).expect("Failed to connect");
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream
+        .write_all(request.as_bytes())
+        .expect("Failed to send request");
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .expect("Failed to read response");
+    let (head, body) = response.split_once("\r\n\r\n").expect("Malformed response");
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .expect("Malformed status line");
+    (status, body.to_string())
+}
+
+#[test]
+fn test_server_applies_transactions_and_reports_outcomes() {
+    let server = start_server("serve");
+    let addr = &server.addr;
+
+    let (status, body) = http_request(
+        addr,
+        "POST",
+        "/transactions",
+        r#"{"type":"deposit","client":1,"tx":1,"amount":5.0}"#,
+    );
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        r#"{"line":1,"client":1,"tx":1,"outcome":"applied","reason":null,"fee":0.0,"replayed":false}"#
+    );
+
+    let (_, body) = http_request(
+        addr,
+        "POST",
+        "/transactions",
+        r#"[{"type":"withdrawal","client":1,"tx":2,"amount":2.0},
+            {"type":"withdrawal","client":1,"tx":3,"amount":9.0}]"#,
+    );
+    assert_eq!(
+        body,
+        r#"[{"line":2,"client":1,"tx":2,"outcome":"applied","reason":null,"fee":0.0,"replayed":false},{"line":3,"client":1,"tx":3,"outcome":"rejected","reason":"Not enough funds for transaction","fee":0.0,"replayed":false}]"#
+    );
+
+    let (status, body) = http_request(addr, "GET", "/accounts/1", "");
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        r#"[{"client":1,"currency":"USD","available":3.0,"held":0.0,"total":3.0,"locked":false}]"#
+    );
+    let (status, _) = http_request(addr, "GET", "/accounts/2", "");
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn test_server_reports_dispute_status() {
+    let server = start_server("serve");
+    let addr = &server.addr;
+
+    http_request(
+        addr,
+        "POST",
+        "/transactions",
+        r#"[{"type":"deposit","client":1,"tx":1,"amount":4.0},
+            {"type":"deposit","client":2,"tx":2,"amount":1.0},
+            {"type":"dispute","client":1,"tx":1}]"#,
+    );
+
+    let (status, body) = http_request(addr, "GET", "/transactions/1", "");
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        r#"{"tx":1,"client":1,"type":"deposit","amount":4.0,"currency":"USD","disputed":4.0,"charged_back":0.0,"refunded":0.0,"disputes":[{"amount":4.0,"held":4.0,"shortfall":0.0,"state":"Open"}]}"#
+    );
+    let (status, _) = http_request(addr, "GET", "/transactions/9", "");
+    assert_eq!(status, 404);
+
+    let (_, body) = http_request(addr, "GET", "/accounts", "");
+    assert_eq!(
+        body,
+        r#"[{"client":1,"currency":"USD","available":0.0,"held":4.0,"total":4.0,"locked":false},{"client":2,"currency":"USD","available":1.0,"held":0.0,"total":1.0,"locked":false}]"#
+    );
+}
+
+// ============ LISTEN TESTS ============
+
+// Opens a connection and sends `rows` without waiting for acknowledgements.
+fn send_rows(addr: &str, rows: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).expect("Failed to connect");
+    stream
+        .write_all(rows.as_bytes())
+        .expect("Failed to send rows");
+    stream
+        .shutdown(Shutdown::Write)
+        .expect("Failed to close connection");
+    stream
+}
+
+fn read_acks(mut stream: TcpStream) -> String {
+    let mut acks = String::new();
+    stream
+        .read_to_string(&mut acks)
+        .expect("Failed to read acknowledgements");
+    acks
+}
+
+#[test]
+fn test_listen_acknowledges_each_row() {
+    let server = start_server("listen");
+    let stream = send_rows(
+        &server.addr,
+        "type,client,tx,amount
+deposit,1,1,5.0
+deposit,1,x,1.0
+withdrawal,1,2,9.0
+resolve,1,1,
+withdrawal,1,3,2.0
+",
+    );
+    let acks = read_acks(stream);
+    let acks: Vec<&str> = acks.lines().collect();
+
+    assert_eq!(acks.len(), 5);
+    assert_eq!(acks[0], "1,accepted");
+    // The malformed row is rejected without closing the connection
+    assert!(acks[1].starts_with("2,rejected,"));
+    assert_eq!(acks[2], "3,rejected,Not enough funds for transaction");
+    // Ignored records are accepted
+    assert_eq!(acks[3], "4,accepted");
+    assert_eq!(acks[4], "5,accepted");
+}
+
+#[test]
+fn test_listen_connections_share_the_engine() {
+    let server = start_server("listen");
+    let first = send_rows(
+        &server.addr,
+        "type,client,tx,amount
+deposit,1,1,5.0
+",
+    );
+    let second = send_rows(
+        &server.addr,
+        "type,client,tx,amount
+deposit,2,2,1.0
+withdrawal,2,3,1.5
+",
+    );
+    assert_eq!(read_acks(first), "1,accepted\n");
+    assert_eq!(
+        read_acks(second),
+        "1,accepted\n2,rejected,Not enough funds for transaction\n"
+    );
+
+    // Funds deposited over the first connection are visible to a later one
+    let third = send_rows(
+        &server.addr,
+        "type,client,tx,amount
+withdrawal,1,4,3.0
+withdrawal,1,5,3.0
+",
+    );
+    assert_eq!(
+        read_acks(third),
+        "1,accepted\n2,rejected,Not enough funds for transaction\n"
+    );
+}
+
+// ============ IDEMPOTENCY TESTS ============
+
+#[test]
+fn test_idempotent_resubmissions_replayed_and_conflicts_rejected() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,1,10.0
+deposit,1,1,12.0
+withdrawal,1,2,3.0
+withdrawal,1,2,3.0
+withdrawal,2,2,3.0
+dispute,1,1,2.0
+dispute,1,1,2.0
+";
+    let path = create_temp_csv("test_idempotent_resubmissions.csv", csv_content);
+    let output = run_file_with_args(&path, &["--idempotent"]);
+
+    // Repeated records apply once; tx 1 and 2 reused with other fields are rejected
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,5.0000,2.0000,7.0000,false
+"
+    );
+}
+
+#[test]
+fn test_idempotency_store_survives_restarts() {
+    let store = "/tmp/test_idempotency_store.csv";
+    std::fs::remove_file(store).ok();
+    let first = create_temp_csv(
+        "test_idempotency_store_first.csv",
+        "type,client,tx,amount
+deposit,1,1,100.0
+",
+    );
+    let second = create_temp_csv(
+        "test_idempotency_store_second.csv",
+        "type,client,tx,amount
+deposit,1,1,100.0
+withdrawal,1,2,40.0
+deposit,1,3,5.0
+deposit,1,3,7.0
+",
+    );
+    let balances = run_file_with_args(&first, &["--idempotency-store", store]);
+    let snapshot = create_temp_csv("test_idempotency_store_balances.csv", &balances);
+    let output = run_file_with_args(
+        &second,
+        &["--idempotency-store", store, "--snapshot", &snapshot],
+    );
+
+    // The deposit is replayed against the balances it left; tx 2 and 3 are new to the store
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,65.0000,0.0000,65.0000,false
+"
+    );
+}
+
+#[test]
+fn test_idempotency_store_needs_snapshot() {
+    let store = "/tmp/test_idempotency_store_no_snapshot.csv";
+    std::fs::remove_file(store).ok();
+    let path = create_temp_csv(
+        "test_idempotency_store_no_snapshot_input.csv",
+        "type,client,tx,amount
+deposit,1,1,100.0
+",
+    );
+    run_file_with_args(&path, &["--idempotency-store", store]);
+    let (output, code) = run_command_with_exit_code(&[&path, "--idempotency-store", store]);
+
+    // Replaying the deposit against empty accounts would lose its funds
+    assert_eq!(output, "");
+    assert_eq!(code, Some(2));
+}
+
+#[test]
+fn test_idempotent_references_by_dispute() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+dispute,1,1,30.0
+resolve,1,1,
+resolve,1,1,
+dispute,1,1,20.0
+resolve,1,1,
+chargeback,1,1,
+authorize,1,2,10.0
+release,1,2,
+release,1,2,4.0
+";
+    let path = create_temp_csv("test_idempotent_references.csv", csv_content);
+    let journal = "/tmp/test_idempotent_references_out.csv";
+    let output = run_file_with_args(&path, &["--idempotent", "--journal", journal]);
+
+    // The second resolve repeats the first; the third resolves the second dispute,
+    // and the chargeback finds no open dispute left
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,100.0000,0.0000,100.0000,false
+"
+    );
+
+    let journal_content = std::fs::read_to_string(journal).expect("Failed to read journal");
+    assert!(journal_content.contains("4,resolve,1,1,USD,,,0.0,ignored,replayed"));
+    assert!(journal_content.contains(
+        "10,release,1,2,USD,4.0,,0.0,rejected,Transaction 2 conflicts with an earlier record"
+    ));
+}
+
+// ============ METRICS TESTS ============
+
+#[test]
+fn test_metrics_written_at_end_of_run() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,20.0
+dispute,1,1,
+chargeback,1,1,
+deposit,1,3,5.0
+resolve,2,9,
+";
+    let path = create_temp_csv("test_metrics_run.csv", csv_content);
+    let metrics_path = "/tmp/test_metrics_run.json";
+    run_file_with_args(&path, &["--metrics", metrics_path]);
+    let metrics: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(metrics_path).expect("Failed to read metrics"),
+    )
+    .expect("Metrics are not JSON");
+
+    assert_eq!(metrics["records"], 6);
+    assert_eq!(metrics["applied"], 3);
+    assert_eq!(metrics["ignored"], 1);
+    assert_eq!(metrics["rejected"], 2);
+    assert_eq!(
+        metrics["rejections"],
+        serde_json::json!({"AccountLocked": 1, "NotEnoughFunds": 1})
+    );
+    assert_eq!(metrics["disputes"], 1);
+    assert_eq!(metrics["chargebacks"], 1);
+}
+
+#[test]
+fn test_server_exposes_prometheus_metrics() {
+    let server = start_server("serve");
+    let addr = &server.addr;
+    http_request(
+        addr,
+        "POST",
+        "/transactions",
+        r#"[{"type":"deposit","client":1,"tx":1,"amount":1.0},
+            {"type":"withdrawal","client":1,"tx":2,"amount":2.0}]"#,
+    );
+
+    let (status, body) = http_request(addr, "GET", "/metrics", "");
+    assert_eq!(status, 200);
+    let lines: Vec<&str> = body.lines().collect();
+    assert!(lines.contains(&"# TYPE transaction_engine_records_total counter"));
+    assert!(lines.contains(&"transaction_engine_records_total{outcome=\"applied\"} 1"));
+    assert!(lines.contains(&"transaction_engine_records_total{outcome=\"rejected\"} 1"));
+    assert!(lines.contains(&"transaction_engine_rejections_total{reason=\"NotEnoughFunds\"} 1"));
+    assert!(lines.contains(&"transaction_engine_queue_depth 0"));
+}
+
+// ============ TRACING TESTS ============
+
+#[test]
+fn test_json_logs_on_stderr_carry_record_spans() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,5.0
+withdrawal,1,2,9.0
+";
+    let path = create_temp_csv("test_json_logs.csv", csv_content);
+    let output = Command::new("cargo")
+        .args(["run", "--", &path, "--log-format", "json"])
+        .env("RUST_LOG", "debug")
+        .output()
+        .expect("Failed to run process");
+    let stdout = str::from_utf8(&output.stdout).expect("Failed to read stdout");
+    let stderr = str::from_utf8(&output.stderr).expect("Failed to read stderr");
+
+    // Stdout only carries the accounts
+    assert_eq!(
+        stdout,
+        "client,available,held,total,locked
+1,5.0000,0.0000,5.0000,false
+"
+    );
+    let rejected = stderr
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .find(|log| log["span"]["name"] == "record" && log["span"]["outcome"] == "rejected")
+        .expect("No log of the rejected record");
+    assert_eq!(rejected["span"]["client"], 1);
+    assert_eq!(rejected["span"]["tx"], 2);
+    assert_eq!(rejected["span"]["line"], 2);
+    assert_eq!(rejected["span"]["type"], "withdrawal");
+    assert_eq!(
+        rejected["fields"]["reason"],
+        "Not enough funds for transaction"
+    );
+}
+
+// ============ PROGRESS TESTS ============
+
+#[test]
+fn test_progress_reported_on_stderr() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,1.0
+withdrawal,2,3,9.0
+";
+    let path = create_temp_csv("test_progress.csv", csv_content);
+    let output = Command::new("cargo")
+        .args(["run", "--", &path, "--progress"])
+        .output()
+        .expect("Failed to run process");
+    let stdout = str::from_utf8(&output.stdout).expect("Failed to read stdout");
+    let stderr = str::from_utf8(&output.stderr).expect("Failed to read stderr");
+
+    assert_eq!(
+        stdout,
+        "client,available,held,total,locked
+1,5.0000,0.0000,5.0000,false
+2,1.0000,0.0000,1.0000,false
+"
+    );
+    // A final report follows the last record
+    let report = stderr
+        .lines()
+        .rfind(|line| line.starts_with("Progress: "))
+        .expect("No progress report");
+    let size = csv_content.len();
+    assert!(report.starts_with(&format!("Progress: {size} B of {size} B (100.0%), ")));
+    assert!(report.ends_with(" records/s, 2 clients, 1 rejected"));
+}
+
+// ============ SUBCOMMAND TESTS ============
+
+#[test]
+fn test_process_command_matches_bare_file() {
+    let path = "src/test/sample_transactions.csv";
+    assert_eq!(run_command(&["process", path]), run_file(path, false));
+}
+
+#[test]
+fn test_validate_reports_invalid_records() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,5.0
+withdrawal,1,2,-1.0
+deposit,2,1,3.0
+exchange,1,3,1.0
+dispute,1,1,
+";
+    let path = create_temp_csv("test_validate.csv", csv_content);
+    let (stdout, code) = run_command_with_exit_code(&["validate", &path]);
+
+    assert_eq!(
+        stdout,
+        "line,reason
+2,Invalid amount for transaction
+3,Duplicate transaction record
+4,Invalid exchange: missing to_currency
+"
+    );
+    assert_eq!(code, Some(6));
+
+    let (stdout, code) =
+        run_command_with_exit_code(&["validate", "src/test/sample_transactions.csv"]);
+    assert_eq!(stdout, "line,reason\n");
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn test_process_rejects_what_validate_reports() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,100.0
+withdrawal,1,2,-100.0
+deposit,2,3,inf
+deposit,2,4,5.0
+";
+    let path = create_temp_csv("test_process_rejects_invalid.csv", csv_content);
+    let journal = "/tmp/test_process_rejects_invalid_out.csv";
+    let output = run_file_with_args(&path, &["--journal", journal]);
+
+    // The negative withdrawal and the infinite deposit move no funds
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,100.0000,0.0000,100.0000,false
+2,5.0000,0.0000,5.0000,false
+"
+    );
+    let journal_content = std::fs::read_to_string(journal).expect("Failed to read journal");
+    assert!(
+        journal_content
+            .contains("2,withdrawal,1,2,USD,-100.0,,0.0,rejected,Invalid amount for transaction")
+    );
+    assert!(
+        journal_content
+            .contains("3,deposit,2,3,USD,inf,,0.0,rejected,Invalid amount for transaction")
+    );
+
+    let (stdout, _) = run_command_with_exit_code(&["validate", &path]);
+    assert_eq!(
+        stdout,
+        "line,reason
+2,Invalid amount for transaction
+3,Invalid amount for transaction
+"
+    );
+}
+
+#[test]
+fn test_generate_is_reproducible_and_processable() {
+    let args = [
+        "generate",
+        "--records",
+        "200",
+        "--clients",
+        "5",
+        "--seed",
+        "7",
+    ];
+    let generated = run_command(&args);
+    assert_eq!(generated, run_command(&args));
+    assert_ne!(
+        generated,
+        run_command(&[
+            "generate",
+            "--records",
+            "200",
+            "--clients",
+            "5",
+            "--seed",
+            "8"
+        ])
+    );
+    assert_eq!(generated.lines().count(), 201);
+
+    let path = create_temp_csv("test_generate.csv", &generated);
+    let (stdout, code) = run_command_with_exit_code(&["validate", &path]);
+    assert_eq!(stdout, "line,reason\n");
+    assert_eq!(code, Some(0));
+}
+
+#[test]
+fn test_replay_rebuilds_accounts_from_journal() {
+    let path = "src/test/sample_transactions.csv";
+    let journal = "/tmp/test_replay_journal.csv";
+    let processed = run_file_with_args(path, &["--journal", journal]);
+
+    assert_eq!(run_command(&["replay", journal]), processed);
+    assert_eq!(
+        run_command(&["replay", journal, "--as-of-line", "2"]),
+        "client,available,held,total,locked
+1,1.0000,0.0000,1.0000,false
+2,2.0000,0.0000,2.0000,false
+"
+    );
+}
+
+#[test]
+fn test_json_output_format() {
+    let output = run_command(&[
+        "process",
+        "src/test/sample_transactions.csv",
+        "--output-format",
+        "json",
+    ]);
+    let accounts: serde_json::Value = serde_json::from_str(&output).expect("Invalid JSON");
+
+    assert_eq!(
+        accounts,
+        serde_json::json!([
+            {"client": 1, "currency": "USD", "available": 1.5, "held": 0.0, "total": 1.5, "locked": false},
+            {"client": 2, "currency": "USD", "available": 2.0, "held": 0.0, "total": 2.0, "locked": false},
+        ])
+    );
+}
+
+#[test]
+fn test_exit_codes_by_failure_category() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,5.0
+withdrawal,1,2,9.0
+";
+    let path = create_temp_csv("test_exit_codes.csv", csv_content);
+    let policy = create_temp_file("test_exit_codes.toml", "exchange_spread = -1.0\n");
+
+    assert_eq!(
+        run_command_with_exit_code(&["process", &path, "--batch"]).1,
+        Some(5)
+    );
+    assert_eq!(
+        run_command_with_exit_code(&["--config", &policy, &path]).1,
+        Some(4)
+    );
+    assert_eq!(
+        run_command_with_exit_code(&["process", "missing.csv"]).1,
+        Some(3)
+    );
+    assert_eq!(
+        run_command_with_exit_code(&["process", "missing.txt"]).1,
+        Some(2)
+    );
+    assert_eq!(
+        run_command_with_exit_code(&[&path, "--log-level", "a=["]).1,
+        Some(2)
+    );
+}
+
+// ============ DRY RUN TESTS ============
+
+const DRY_RUN_TRANSACTIONS: &str = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+withdrawal,2,3,9.0
+dispute,1,1,
+chargeback,1,1,
+resolve,2,2,
+";
+
+#[test]
+fn test_dry_run_reports_instead_of_balances() {
+    let path = create_temp_csv("test_dry_run.csv", DRY_RUN_TRANSACTIONS);
+    let output = run_command(&["process", &path, "--dry-run"]);
+
+    assert_eq!(
+        output,
+        "Records: 6 (4 applied, 1 ignored, 1 rejected)
+  chargeback: 1 applied, 0 ignored, 0 rejected
+  deposit: 2 applied, 0 ignored, 0 rejected
+  dispute: 1 applied, 0 ignored, 0 rejected
+  resolve: 0 applied, 1 ignored, 0 rejected
+  withdrawal: 0 applied, 0 ignored, 1 rejected
+Rejections:
+  line 3: withdrawal of client 2, tx 3: Not enough funds for transaction
+Clients locked: 1
+Net movement:
+  USD: +5.0000
+"
+    );
+}
+
+#[test]
+fn test_dry_run_reports_exchanges_and_locks_per_currency() {
+    let csv_content = "type,client,tx,amount,currency,to_currency,timestamp
+deposit,1,1,100.0,EUR,,1700000000
+exchange,1,2,40.0,EUR,USD,1700000000
+dispute,2,3,,USD,,1700000000
+chargeback,2,3,,USD,,1700000000
+";
+    let path = create_temp_csv("test_dry_run_exchanges.csv", csv_content);
+    let rates = create_temp_file("test_dry_run_exchanges_rates.csv", RATES);
+    let snapshot = create_temp_csv(
+        "test_dry_run_exchanges_snapshot.csv",
+        "client,currency,available,held,total,locked
+2,EUR,5.0,0.0,5.0,true
+2,USD,10.0,0.0,10.0,false
+",
+    );
+    let index = create_temp_csv(
+        "test_dry_run_exchanges_index.csv",
+        "client,tx,currency,amount,disputed,timestamp
+2,3,USD,10.0,0.0,1700000000
+",
+    );
+    let output = run_command(&[
+        &path,
+        "--dry-run",
+        "--rates",
+        &rates,
+        "--snapshot",
+        &snapshot,
+        "--snapshot-transactions",
+        &index,
+        "--output-format",
+        "json",
+    ]);
+    let report: serde_json::Value = serde_json::from_str(&output).expect("Invalid JSON");
+
+    // Client 2 was only locked in EUR before the chargeback locked its USD account
+    assert_eq!(report["locked"], serde_json::json!([2]));
+    assert_eq!(
+        report["net_movement"],
+        serde_json::json!({"EUR": 100.0, "USD": -10.0})
+    );
+    assert_eq!(
+        report["exchanged"],
+        serde_json::json!({"EUR": -40.0, "USD": 42.0})
+    );
+}
+
+#[test]
+fn test_dry_run_does_not_persist() {
+    let path = create_temp_csv("test_dry_run_persist.csv", DRY_RUN_TRANSACTIONS);
+    let store = "/tmp/test_dry_run_persist_store.csv";
+    std::fs::remove_file(store).ok();
+    let output = run_command(&[
+        &path,
+        "--dry-run",
+        "--idempotency-store",
+        store,
+        "--output-format",
+        "json",
+    ]);
+    let report: serde_json::Value = serde_json::from_str(&output).expect("Invalid JSON");
+
+    assert_eq!(report["types"]["deposit"]["applied"], 2);
+    assert_eq!(report["locked"], serde_json::json!([1]));
+    assert_eq!(report["net_movement"]["USD"], 5.0);
+    assert!(!std::path::Path::new(store).exists());
+
+    let (_, success) = run_command_with_status(&[&path, "--dry-run", "--journal", "/tmp/x.csv"]);
+    assert!(!success);
+}
+
+#[test]
+fn test_statement_and_reconcile_do_not_persist() {
+    let path = create_temp_csv("test_statement_persist.csv", DRY_RUN_TRANSACTIONS);
+    let store = "/tmp/test_statement_persist_store.csv";
+    std::fs::remove_file(store).ok();
+    run_command(&["statement", &path, "--idempotency-store", store]);
+    run_command_with_status(&["reconcile", &path, &path, "--idempotency-store", store]);
+
+    assert!(!std::path::Path::new(store).exists());
+}
+
+#[test]
+fn test_dry_run_of_halted_batch_applies_nothing() {
+    let csv_content = "type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,20.0
+deposit,1,3,5.0
+";
+    let path = create_temp_csv("test_dry_run_halted.csv", csv_content);
+    let (output, code) = run_command_with_exit_code(&["process", &path, "--dry-run", "--batch"]);
+
+    // The deposit before the halt is rolled back with the rest of the batch
+    assert_eq!(
+        output,
+        "Records: 2 (1 applied, 0 ignored, 1 rejected)
+Batch halted: no record would be applied
+  deposit: 1 applied, 0 ignored, 0 rejected
+  withdrawal: 0 applied, 0 ignored, 1 rejected
+Rejections:
+  line 2: withdrawal of client 1, tx 2: Not enough funds for transaction
+Clients locked: none
+Net movement:
+  USD: +0.0000
+"
+    );
+    assert_eq!(code, Some(5));
+}
+
+// ============ SNAPSHOT TESTS ============
+
+#[test]
+fn test_snapshot_continues_from_previous_run() {
+    let day_one = create_temp_csv(
+        "test_snapshot_day_one.csv",
+        "type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+dispute,2,2,
+deposit,3,3,4.0
+dispute,3,3,
+chargeback,3,3,
+",
+    );
+    let index = "/tmp/test_snapshot_index.csv";
+    let accounts = create_temp_csv(
+        "test_snapshot_accounts.csv",
+        &run_file_with_args(&day_one, &["--transaction-index", index]),
+    );
+    let day_two = create_temp_csv(
+        "test_snapshot_day_two.csv",
+        "type,client,tx,amount
+deposit,1,4,1.0
+resolve,2,2,
+dispute,1,1,
+deposit,1,1,3.0
+deposit,3,5,1.0
+",
+    );
+    let output = run_file_with_args(
+        &day_two,
+        &["--snapshot", &accounts, "--snapshot-transactions", index],
+    );
+
+    // Deposits of the first day can be disputed and resolved, their tx ids
+    // stay taken, and locked accounts stay locked.
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,1.0000,10.0000,11.0000,false
+2,5.0000,0.0000,5.0000,false
+3,0.0000,0.0000,0.0000,true
+"
+    );
+}
+
+#[test]
+fn test_snapshot_keeps_open_authorizations() {
+    let day_one = create_temp_csv(
+        "test_snapshot_authorizations_day_one.csv",
+        "type,client,tx,amount
+deposit,1,1,100.0
+authorize,1,2,30.0
+deposit,2,3,50.0
+authorize,2,4,20.0
+capture,2,4,5.0
+",
+    );
+    let index = "/tmp/test_snapshot_authorizations_index.csv";
+    let accounts = create_temp_csv(
+        "test_snapshot_authorizations_accounts.csv",
+        &run_file_with_args(&day_one, &["--transaction-index", index]),
+    );
+    let day_two = create_temp_csv(
+        "test_snapshot_authorizations_day_two.csv",
+        "type,client,tx,amount
+capture,1,2,30.0
+release,2,4,
+",
+    );
+    let output = run_file_with_args(
+        &day_two,
+        &["--snapshot", &accounts, "--snapshot-transactions", index],
+    );
+
+    // The holds of the first day can still be captured and released
+    assert_eq!(
+        output,
+        "client,available,held,total,locked
+1,70.0000,0.0000,70.0000,false
+2,45.0000,0.0000,45.0000,false
+"
+    );
+}
+
+#[test]
+fn test_snapshot_keeps_partial_disputes_under_hold_policy() {
+    let day_one = create_temp_csv(
+        "test_snapshot_hold_day_one.csv",
+        "type,client,tx,amount,dispute
+deposit,1,1,100.0,
+withdrawal,1,2,80.0,
+dispute,1,1,50.0,
+dispute,1,1,30.0,
+resolve,1,1,,1
+",
+    );
+    let index = "/tmp/test_snapshot_hold_index.csv";
+    let accounts = create_temp_csv(
+        "test_snapshot_hold_accounts.csv",
+        &run_file_with_args(
+            &day_one,
+            &["--dispute-policy", "hold", "--transaction-index", index],
+        ),
+    );
+    let day_two = create_temp_csv(
+        "test_snapshot_hold_day_two.csv",
+        "type,client,tx,amount,dispute
+dispute,1,1,10.0,
+chargeback,1,1,,2
+",
+    );
+    let output = run_file_with_args(
+        &day_two,
+        &[
+            "--dispute-policy",
+            "hold",
+            "--snapshot",
+            &accounts,
+            "--snapshot-transactions",
+            index,
+        ],
+    );
+
+    // Dispute 2 held nothing of its 30, so charging it back keeps the 10 held
+    // by dispute 3 and the 30 shortfall, as in a single run.
+    assert_eq!(
+        std::fs::read_to_string(index).unwrap(),
+        "type,client,tx,currency,amount,disputed,timestamp,disputes_raised,dispute,held,shortfall
+deposit,1,1,USD,100.0,30.0,,2,,,0.0
+dispute,1,1,USD,30.0,0.0,,0,2,0.0,30.0
+"
+    );
+    assert_eq!(
+        output,
+        "client,available,held,total,locked,shortfall
+1,10.0000,10.0000,20.0000,true,30.0000
+"
+    );
+}
+
+#[test]
+fn test_snapshot_with_inconsistent_balances_rejected() {
+    let snapshot = create_temp_csv(
+        "test_snapshot_inconsistent.csv",
+        "client,available,held,total,locked
+1,1.0,0.0,2.0,false
+",
+    );
+    let (stdout, code) =
+        run_command_with_exit_code(&["src/test/sample_transactions.csv", "--snapshot", &snapshot]);
+
+    assert_eq!(stdout, "");
+    assert_eq!(code, Some(3));
+
+    let snapshot = create_temp_csv(
+        "test_snapshot_dispute_not_held.csv",
+        "client,available,held,total,locked
+1,1.0,0.0,1.0,false
+",
+    );
+    let index = create_temp_csv(
+        "test_snapshot_dispute_not_held_index.csv",
+        "client,tx,amount,disputed
+1,1,1.0,1.0
+",
+    );
+    let (_, code) = run_command_with_exit_code(&[
+        "src/test/sample_transactions.csv",
+        "--snapshot",
+        &snapshot,
+        "--snapshot-transactions",
+        &index,
+    ]);
+    assert_eq!(code, Some(3));
+    // Held funds neither disputed nor authorized in the index
+    let snapshot = create_temp_csv(
+        "test_snapshot_unexplained_held.csv",
+        "client,available,held,total,locked
+1,1.0,2.0,3.0,false
+",
+    );
+    let (_, code) =
+        run_command_with_exit_code(&["src/test/sample_transactions.csv", "--snapshot", &snapshot]);
+    assert_eq!(code, Some(3));
+}
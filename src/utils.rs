@@ -7,3 +7,13 @@ pub fn validate_file_path(file_path: &str) -> Result<String, AppError> {
 
     Ok(file_path.to_string())
 }
+
+/// Parses a percentage of at least 0 and below 100.
+pub fn validate_percentage(percent: &str) -> Result<f64, AppError> {
+    match percent.parse::<f64>() {
+        Ok(value) if (0.0..100.0).contains(&value) => Ok(value),
+        _ => Err(AppError::WrongArgument(format!(
+            "{percent} is not a percentage below 100"
+        ))),
+    }
+}